@@ -1,84 +1,142 @@
+use log::{error, info, warn};
 use utils::PriceList;
 
+use crate::decimal::Decimal;
+use crate::exchange;
+
+pub use utils::{Consensus, Primaries, Weighting};
+
 mod utils;
 
-pub fn average(data: Vec<f64>) -> (bool, f64) {
+pub fn average(data: Vec<Decimal>) -> (bool, Decimal) {
     let pl = PriceList::new(data, vec![]);
     let average = match pl.average() {
         Ok(a) => a,
-        Err(_) => return (false, 0.0),
+        Err(_) => return (false, Decimal::ZERO),
     };
     println!("average {:?}", average);
     (true, average)
 }
 
-pub fn median(data: Vec<f64>) -> (bool, f64) {
+pub fn median(data: Vec<Decimal>) -> (bool, Decimal) {
     let p1 = PriceList::new(data, vec![]);
     let median = match p1.median() {
         Ok(m) => m,
-        Err(_) => return (false, 0.0),
+        Err(_) => return (false, Decimal::ZERO),
     };
     println!("median {:?}", median);
     (true, median)
 }
 
+fn source_names(sources: &Vec<usize>) -> Vec<&'static str> {
+    sources.iter().map(|s| exchange::source_name(*s)).collect()
+}
+
 pub fn backwad(
-    data: Vec<f64>,
-    diff_percent: u16,
-    expected_ratio: u16,
-) -> (bool, f64) {
+    data: Vec<Decimal>,
+    primaries: &Primaries,
+    diff_bps: u32,
+    expected_ratio_bps: u32,
+) -> (bool, Decimal) {
     let p1 = PriceList::new(data, vec![]);
-    let backwad = match p1.backwad(diff_percent, expected_ratio) {
+    let backwad = match p1.backwad(primaries, diff_bps, expected_ratio_bps) {
         Ok(b) => b,
-        Err(_) => return (false, 0.0),
+        Err(e) => {
+            error!("backwad: {}", e);
+            return (false, Decimal::ZERO);
+        }
     };
-    println!("backwad {:?}", backwad);
-    (true, backwad)
+    if backwad.fallback {
+        warn!(
+            "backwad: primary {} missing, fell back to {}",
+            exchange::source_name(primaries.sources[0]),
+            exchange::source_name(backwad.primary)
+        );
+    }
+    info!(
+        "backwad {} from {}, agreed {:?}, disagreed {:?}, ratio {} bps",
+        backwad.price,
+        exchange::source_name(backwad.primary),
+        source_names(&backwad.agreed),
+        source_names(&backwad.disagreed),
+        backwad.ratio_bps
+    );
+    (true, backwad.price)
 }
 
-pub fn weighted(data: Vec<f64>, volume: Vec<f64>) -> (bool, f64) {
+pub fn weighted(data: Vec<Decimal>, volume: Vec<Decimal>) -> (bool, Decimal) {
     let pl = PriceList::new(data, volume);
     let average = match pl.weighted_average() {
         Ok(a) => a,
-        Err(_) => return (false, 0.0),
+        Err(_) => return (false, Decimal::ZERO),
     };
     println!("weighted average {:?}", average);
     (true, average)
 }
 
-pub fn max(data: Vec<f64>) -> (bool, f64) {
+pub fn liquidity(
+    symbol: &str,
+    data: Vec<Decimal>,
+    volume: Vec<Decimal>,
+    weighting: &Weighting,
+) -> (bool, Decimal) {
+    let pl = PriceList::new(data, volume);
+    let weighted = match pl.liquidity_weighted(weighting) {
+        Ok(w) => w,
+        Err(e) => {
+            error!("liquidity {}: {}", symbol, e);
+            return (false, Decimal::ZERO);
+        }
+    };
+    let weights: Vec<(&str, Decimal)> = weighted
+        .weights
+        .iter()
+        .map(|(i, w)| (exchange::source_name(*i), w.round_dp(4)))
+        .collect();
+    info!(
+        "liquidity {} = {} weights {:?}",
+        symbol, weighted.price, weights
+    );
+    (true, weighted.price)
+}
+
+pub fn max(data: Vec<Decimal>) -> (bool, Decimal) {
     let p1 = PriceList::new(data, vec![]);
     let m = match p1.max() {
         Ok(m) => m,
-        Err(_) => return (false, 0.0),
+        Err(_) => return (false, Decimal::ZERO),
     };
     println!("max {:?}", m);
     (true, m)
 }
 
 pub fn switch_algo(
+    symbol: &str,
     algo: &str,
-    data: Vec<f64>,
-    volume: Vec<f64>,
+    data: Vec<Decimal>,
+    volume: Vec<Decimal>,
     diff_percent: Option<f64>,
     expected_ratio: Option<f64>,
-) -> (bool, f64) {
-    let mut diff = 0u16;
-    let mut ratio = 0u16;
+    primaries: &Primaries,
+    weighting: &Weighting,
+) -> (bool, Decimal) {
+    let mut diff = 0u32;
+    let mut ratio = 0u32;
 
     if algo == "backwad" {
         if diff_percent.is_none() || expected_ratio.is_none() {
-            return (false, 0.0);
+            return (false, Decimal::ZERO);
         }
-        diff = (diff_percent.unwrap() * 100.0) as u16;
-        ratio = (expected_ratio.unwrap() * 100.0) as u16;
+        diff = (diff_percent.unwrap() * 10000.0).round() as u32;
+        ratio = (expected_ratio.unwrap() * 10000.0).round() as u32;
     }
     match algo {
         "average" => average(data),
         "median" => median(data),
-        "backwad" => backwad(data, diff, ratio),
+        "backwad" => backwad(data, primaries, diff, ratio),
         "weighted" => weighted(data, volume),
+        "liquidity" => liquidity(symbol, data, volume, weighting),
         "max" => max(data),
-        _ => (false, 0.0),
+        _ => (false, Decimal::ZERO),
     }
 }
@@ -1,6 +1,8 @@
 use anyhow::{self, Ok, Result};
 use thiserror::Error;
 
+use crate::decimal::{Decimal, BPS};
+
 #[derive(Error, Debug)]
 pub enum DataError {
     #[error("divide by zero")]
@@ -9,134 +11,308 @@ pub enum DataError {
     DataLenTooShort(usize),
     #[error("master price missing")]
     MasterPriceMissing,
-    #[error("actual ratio {0} too low")]
-    ActualRatioTooLow(u16),
+    #[error("actual ratio {0} bps too low")]
+    ActualRatioTooLow(u32),
+    #[error("arithmetic overflow")]
+    Overflow,
+}
+
+/// Ordered storage slots used as the reference price by `backwad`.
+#[derive(Debug, Clone)]
+pub struct Primaries {
+    pub sources: Vec<usize>,
+    pub min_sources: usize,
+    pub fallback: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Consensus {
+    pub price: Decimal,
+    pub primary: usize,
+    pub fallback: bool,
+    pub agreed: Vec<usize>,
+    pub disagreed: Vec<usize>,
+    pub ratio_bps: u32,
+}
+
+/// Per-source inputs of the liquidity weighted average, indexed by storage
+/// slot like the prices themselves.
+#[derive(Debug, Clone)]
+pub struct Weighting {
+    pub ages: Vec<u64>,
+    pub usd_rates: Vec<Decimal>,
+    pub trust: Vec<Decimal>,
+    pub half_life: u64,
+    pub max_weight: Decimal,
+}
+
+#[derive(Debug, Clone)]
+pub struct Weighted {
+    pub price: Decimal,
+    pub weights: Vec<(usize, Decimal)>,
+}
+
+/// Normalizes weights to sum to one, capping each at `cap` and handing the
+/// excess to the uncapped ones. When the cap cannot be met by every source
+/// all of them end up with the same weight.
+fn cap_weights(raw: &Vec<(usize, Decimal)>, cap: Decimal) -> Vec<Decimal> {
+    let len = raw.len();
+    let mut weights = vec![Decimal::ZERO; len];
+    let mut capped = vec![false; len];
+
+    if cap * Decimal::from(len) <= Decimal::ONE {
+        return vec![Decimal::ONE / Decimal::from(len); len];
+    }
+
+    loop {
+        let fixed = Decimal::from(capped.iter().filter(|c| **c).count()) * cap;
+        let free_sum: Decimal = raw
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !capped[*i])
+            .map(|(_, (_, w))| *w)
+            .sum();
+
+        let mut changed = false;
+        for i in 0..len {
+            if capped[i] {
+                weights[i] = cap;
+                continue;
+            }
+            weights[i] = (Decimal::ONE - fixed) * raw[i].1 / free_sum;
+            if weights[i] > cap {
+                capped[i] = true;
+                changed = true;
+            }
+        }
+        if !changed {
+            return weights;
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct PriceList {
-    pub data: Vec<f64>,
-    pub volume: Vec<f64>,
+    pub data: Vec<Decimal>,
+    pub volume: Vec<Decimal>,
 }
 
 impl PriceList {
-    pub fn new(data: Vec<f64>, volume: Vec<f64>) -> PriceList {
+    pub fn new(data: Vec<Decimal>, volume: Vec<Decimal>) -> PriceList {
         PriceList { data, volume }
     }
 
-    pub fn median(&self) -> Result<f64> {
-        let mut v: Vec<f64> = self
-            .data
+    fn non_zero(&self) -> Vec<Decimal> {
+        self.data
             .clone()
             .into_iter()
-            .filter(|x| *x != 0.0)
-            .collect();
+            .filter(|x| !x.is_zero())
+            .collect()
+    }
+
+    fn sum(v: &[Decimal]) -> Result<Decimal> {
+        let mut sum = Decimal::ZERO;
+        for x in v.iter() {
+            sum = sum.checked_add(*x).ok_or(DataError::Overflow)?;
+        }
+        Ok(sum)
+    }
+
+    pub fn median(&self) -> Result<Decimal> {
+        let mut v = self.non_zero();
 
         let len = v.len();
         if len == 0 {
             return Err(DataError::DivideByZero.into());
         }
 
-        v.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        v.sort();
         let d = (len / 2) as usize;
         let median = match len % 2 {
             1 => v[d],
             _ => {
                 let p = d - 1;
-                (v[p] + v[d]) / 2.0
+                v[p].checked_add(v[d]).ok_or(DataError::Overflow)?
+                    / Decimal::TWO
             }
         };
         Ok(median)
     }
 
-    pub fn average(&self) -> Result<f64> {
-        let v: Vec<f64> = self
-            .data
-            .clone()
-            .into_iter()
-            .filter(|x| *x != 0.0)
-            .collect();
-        let sum = v.iter().sum::<f64>();
+    pub fn average(&self) -> Result<Decimal> {
+        let v = self.non_zero();
+        let sum = Self::sum(&v)?;
         let len = v.len();
         if len == 0 {
             return Err(DataError::DivideByZero.into());
         }
-        Ok(sum / len as f64)
+        Ok(sum / Decimal::from(len))
     }
 
-    pub fn weighted_average(&self) -> Result<f64> {
-        let weight_sum = self.volume.iter().sum::<f64>();
-        let mut weight_price = 0.0f64;
+    pub fn weighted_average(&self) -> Result<Decimal> {
+        let weight_sum = Self::sum(&self.volume)?;
+        let mut weight_price = Decimal::ZERO;
 
         for (i, v) in self.data.iter().enumerate() {
-            if *v == 0.0 || self.volume[i] == 0.0 {
+            if v.is_zero() || self.volume[i].is_zero() {
                 continue;
             }
-            weight_price += v * self.volume[i];
+            let w = v.checked_mul(self.volume[i]).ok_or(DataError::Overflow)?;
+            weight_price =
+                weight_price.checked_add(w).ok_or(DataError::Overflow)?;
         }
 
-        if weight_sum == 0.0 || weight_price == 0.0 {
+        if weight_sum.is_zero() || weight_price.is_zero() {
             return Err(DataError::DivideByZero.into());
         }
-        Ok(weight_price / weight_sum)
+        Ok(weight_price
+            .checked_div(weight_sum)
+            .ok_or(DataError::Overflow)?)
     }
 
+    /// Checks the quotes against the first available primary source and
+    /// publishes the primary's price when enough sources agree with it.
+    /// Differences and ratios are in basis points.
     pub fn backwad(
         &self,
-        diff_percent: u16,
-        expected_ratio: u16,
-    ) -> Result<f64> {
-        let len = self.data.len();
-        if len < 4 {
-            return Err(DataError::DataLenTooShort(len).into());
+        primaries: &Primaries,
+        diff_bps: u32,
+        expected_ratio_bps: u32,
+    ) -> Result<Consensus> {
+        let v = self.non_zero();
+        let vlen = v.len();
+        if vlen < primaries.min_sources {
+            return Err(DataError::DataLenTooShort(vlen).into());
+        }
+
+        let mut primary = None;
+        for (i, source) in primaries.sources.iter().enumerate() {
+            let price = self.data.get(*source).copied();
+            if price.map_or(false, |p| !p.is_zero()) {
+                primary = Some((*source, i > 0));
+                break;
+            }
+            if !primaries.fallback {
+                break;
+            }
         }
 
-        let mut master_price = self.data[0];
-        if master_price == 0.0f64 {
-            master_price = self.data[1];
+        let (primary, fallback) = match primary {
+            Some(p) => p,
+            None => return Err(DataError::MasterPriceMissing.into()),
+        };
+        let master_price = self.data[primary];
+
+        let max_diff = Decimal::from(diff_bps);
+        let mut agreed = Vec::new();
+        let mut disagreed = Vec::new();
+        for (i, d) in self.data.iter().enumerate() {
+            if d.is_zero() {
+                continue;
+            }
+            let diff = (master_price - *d).abs();
+            let diff_bps = diff.checked_mul(BPS).ok_or(DataError::Overflow)?
+                / master_price;
+            if diff_bps <= max_diff {
+                agreed.push(i);
+            } else {
+                disagreed.push(i);
+            }
         }
 
-        if master_price == 0.0f64 {
-            return Err(DataError::MasterPriceMissing.into());
+        let actual_ratio = (agreed.len() as u64 * 10000 / vlen as u64) as u32;
+        if actual_ratio < expected_ratio_bps {
+            return Err(DataError::ActualRatioTooLow(actual_ratio).into());
         }
 
-        let v: Vec<f64> = self
-            .data
-            .clone()
-            .into_iter()
-            .filter(|x| *x != 0.0)
-            .collect();
+        Ok(Consensus {
+            price: master_price,
+            primary,
+            fallback,
+            agreed,
+            disagreed,
+            ratio_bps: actual_ratio,
+        })
+    }
 
-        let vlen = v.len();
-        let mut actual_len: usize = 0;
-        for d in v.iter() {
-            let diff = master_price - *d;
-            let diff_pre = (diff.abs() * 100.0 / master_price) as u16;
-            if diff_pre <= diff_percent {
-                actual_len += 1;
+    /// Usd volume of each quoted source. Sources that report no volume
+    /// (coinbase, kraken, crypto) get the median of the others, or one when
+    /// none reports any, instead of dropping out.
+    fn usd_volumes(&self, w: &Weighting) -> Result<Vec<Decimal>> {
+        let mut usd = vec![Decimal::ZERO; self.data.len()];
+        let mut reported = Vec::new();
+        for (i, p) in self.data.iter().enumerate() {
+            let volume = self.volume.get(i).copied().unwrap_or_default();
+            usd[i] = p
+                .checked_mul(volume)
+                .and_then(|v| v.checked_mul(w.usd_rates[i]))
+                .ok_or(DataError::Overflow)?;
+            if !usd[i].is_zero() {
+                reported.push(usd[i]);
             }
         }
 
-        let actual_ratio = (actual_len as f64 * 100.0 / vlen as f64) as u16;
-        if actual_ratio < expected_ratio {
-            return Err(DataError::ActualRatioTooLow(actual_ratio).into());
+        let fallback = match reported.is_empty() {
+            true => Decimal::ONE,
+            false => PriceList::new(reported, vec![]).median()?,
+        };
+        for (i, p) in self.data.iter().enumerate() {
+            if !p.is_zero() && usd[i].is_zero() {
+                usd[i] = fallback;
+            }
         }
+        Ok(usd)
+    }
+
+    /// Weights each source by its usd volume, the freshness of its quote
+    /// (halving every `half_life` seconds) and its configured trust.
+    pub fn liquidity_weighted(&self, w: &Weighting) -> Result<Weighted> {
+        let usd = self.usd_volumes(w)?;
+        let mut raw = Vec::new();
+        for (i, p) in self.data.iter().enumerate() {
+            if p.is_zero() || w.trust[i].is_zero() {
+                continue;
+            }
 
-        Ok(master_price)
+            let age = w.ages[i] as f64 / 1000.0;
+            let decay = 0.5f64.powf(age / w.half_life.max(1) as f64);
+            let decay = Decimal::try_from(decay).unwrap_or(Decimal::ZERO);
+
+            let weight = usd[i]
+                .checked_mul(w.trust[i])
+                .and_then(|v| v.checked_mul(decay))
+                .ok_or(DataError::Overflow)?;
+            if !weight.is_zero() {
+                raw.push((i, weight));
+            }
+        }
+
+        if raw.is_empty() {
+            return Err(DataError::DivideByZero.into());
+        }
+
+        let weights = cap_weights(&raw, w.max_weight);
+        let mut price = Decimal::ZERO;
+        for (k, (i, _)) in raw.iter().enumerate() {
+            let part = self.data[*i]
+                .checked_mul(weights[k])
+                .ok_or(DataError::Overflow)?;
+            price = price.checked_add(part).ok_or(DataError::Overflow)?;
+        }
+
+        Ok(Weighted {
+            price,
+            weights: raw.iter().map(|(i, _)| *i).zip(weights).collect(),
+        })
     }
 
-    pub fn max(&self) -> Result<f64> {
-        let v: Vec<f64> = self
-            .data
-            .clone()
-            .into_iter()
-            .filter(|x| *x != 0.0)
-            .collect();
+    pub fn max(&self) -> Result<Decimal> {
+        let v = self.non_zero();
         let len = v.len();
         if len == 0 {
             return Err(DataError::DataLenTooShort(len).into());
         }
-        let mut m = 0.0f64;
+        let mut m = Decimal::ZERO;
         for i in 0..len {
             if v[i] > m {
                 m = v[i];
@@ -145,3 +321,137 @@ impl PriceList {
         Ok(m)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decimal;
+
+    fn d(s: &str) -> Decimal {
+        decimal::parse(s).unwrap()
+    }
+
+    fn primaries(fallback: bool) -> Primaries {
+        Primaries {
+            sources: vec![0, 1],
+            min_sources: 3,
+            fallback,
+        }
+    }
+
+    fn quotes(first: &str) -> PriceList {
+        let data =
+            vec![d(first), d("100.1"), d("100.2"), Decimal::ZERO, d("101")];
+        PriceList::new(data, vec![])
+    }
+
+    fn error(result: Result<Consensus>) -> DataError {
+        result.unwrap_err().downcast::<DataError>().unwrap()
+    }
+
+    #[test]
+    fn backwad_threshold_is_inclusive() {
+        // 100.1 is exactly 10 bps off the primary and agrees, the rest
+        // do not: two of four quotes.
+        let consensus =
+            quotes("100").backwad(&primaries(false), 10, 5000).unwrap();
+        assert_eq!(consensus.price, d("100"));
+        assert_eq!(consensus.primary, 0);
+        assert!(!consensus.fallback);
+        assert_eq!(consensus.agreed, vec![0, 1]);
+        assert_eq!(consensus.disagreed, vec![2, 4]);
+        assert_eq!(consensus.ratio_bps, 5000);
+
+        assert!(matches!(
+            error(quotes("100").backwad(&primaries(false), 10, 5001)),
+            DataError::ActualRatioTooLow(5000)
+        ));
+        assert!(matches!(
+            error(quotes("100").backwad(&primaries(false), 9, 5000)),
+            DataError::ActualRatioTooLow(2500)
+        ));
+    }
+
+    #[test]
+    fn backwad_falls_back_to_the_next_primary() {
+        let mut pl = quotes("100");
+        pl.data[0] = Decimal::ZERO;
+        let consensus = pl.backwad(&primaries(true), 10, 0).unwrap();
+        assert_eq!(consensus.price, d("100.1"));
+        assert_eq!(consensus.primary, 1);
+        assert!(consensus.fallback);
+        assert_eq!(consensus.agreed, vec![1, 2]);
+
+        assert!(matches!(
+            error(pl.backwad(&primaries(false), 10, 0)),
+            DataError::MasterPriceMissing
+        ));
+    }
+
+    #[test]
+    fn backwad_needs_min_sources() {
+        let mut pl = quotes("100");
+        pl.data[2] = Decimal::ZERO;
+        pl.data[4] = Decimal::ZERO;
+        assert!(matches!(
+            error(pl.backwad(&primaries(false), 10, 0)),
+            DataError::DataLenTooShort(2)
+        ));
+    }
+
+    fn weighting(ages: Vec<u64>, max_weight: &str) -> Weighting {
+        let len = ages.len();
+        Weighting {
+            ages,
+            usd_rates: vec![Decimal::ONE; len],
+            trust: vec![Decimal::ONE; len],
+            half_life: 60,
+            max_weight: d(max_weight),
+        }
+    }
+
+    #[test]
+    fn cap_hands_excess_to_the_rest() {
+        let raw = vec![(0, d("8")), (1, d("1")), (2, d("1"))];
+        let weights = cap_weights(&raw, d("0.5"));
+        assert_eq!(weights, vec![d("0.5"), d("0.25"), d("0.25")]);
+        // Nothing above the cap: plain normalization.
+        let weights = cap_weights(&raw, Decimal::ONE);
+        assert_eq!(weights, vec![d("0.8"), d("0.1"), d("0.1")]);
+    }
+
+    #[test]
+    fn unreachable_cap_weighs_equally() {
+        let raw = vec![(0, d("8")), (1, d("1")), (2, d("1"))];
+        let weights = cap_weights(&raw, d("0.3"));
+        let third = Decimal::ONE / Decimal::from(3);
+        assert_eq!(weights, vec![third; 3]);
+    }
+
+    #[test]
+    fn stale_quotes_decay() {
+        // Equal usd volumes; the second quote is one half life old.
+        let pl =
+            PriceList::new(vec![d("100"), d("110")], vec![d("1.1"), d("1")]);
+        let weighted = pl
+            .liquidity_weighted(&weighting(vec![0, 60000], "1"))
+            .unwrap();
+        assert_eq!(weighted.weights[0].1.round_dp(8), d("0.66666667"));
+        assert_eq!(weighted.weights[1].1.round_dp(8), d("0.33333333"));
+        assert_eq!(weighted.price.round_dp(8), d("103.33333333"));
+    }
+
+    #[test]
+    fn zero_liquidity_weighs_equally() {
+        let pl = PriceList::new(vec![d("100"), d("110")], vec![]);
+        let weighted =
+            pl.liquidity_weighted(&weighting(vec![0, 0], "1")).unwrap();
+        assert_eq!(weighted.weights, vec![(0, d("0.5")), (1, d("0.5"))]);
+        assert_eq!(weighted.price, d("105"));
+
+        // Without any trusted source there is nothing to weigh.
+        let mut w = weighting(vec![0, 0], "1");
+        w.trust = vec![Decimal::ZERO; 2];
+        assert!(pl.liquidity_weighted(&w).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+
+pub const DEFAULT_HYSTERESIS: f64 = 0.1;
+pub const DEFAULT_RUNWAY_WINDOW: u64 = 86400;
+const DAY_MS: f64 = 86400000.0;
+
+/// Balance thresholds of one account, in whole coins.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Thresholds {
+    pub warning: Option<f64>,
+    pub critical: Option<f64>,
+    /// Margin above a threshold before it is considered cleared, as a
+    /// fraction. Defaults to 0.1.
+    pub hysteresis: Option<f64>,
+    /// Seconds of balance history used to estimate the spend rate.
+    /// Defaults to a day.
+    pub runway_window: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Ok => write!(f, "ok"),
+            Level::Warning => write!(f, "warning"),
+            Level::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// Tracks the balance of one account and reports level changes.
+#[derive(Debug)]
+pub struct Monitor {
+    thresholds: Thresholds,
+    level: Level,
+    samples: VecDeque<(u64, f64)>,
+}
+
+impl Monitor {
+    pub fn new(thresholds: Thresholds) -> Monitor {
+        Monitor {
+            thresholds,
+            level: Level::Ok,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// The threshold crossed to reach `level`.
+    pub fn threshold(&self, level: Level) -> Option<f64> {
+        match level {
+            Level::Ok => None,
+            Level::Warning => self.thresholds.warning,
+            Level::Critical => self.thresholds.critical,
+        }
+    }
+
+    /// Level of `balance`. Leaving a level needs the balance to clear its
+    /// threshold by the hysteresis margin, so a balance hovering around a
+    /// threshold does not flap.
+    fn classify(&self, balance: f64) -> Level {
+        let margin =
+            1.0 + self.thresholds.hysteresis.unwrap_or(DEFAULT_HYSTERESIS);
+        let below = |level: Level| match self.threshold(level) {
+            Some(t) if self.level >= level => balance < t * margin,
+            Some(t) => balance < t,
+            None => false,
+        };
+        if below(Level::Critical) {
+            Level::Critical
+        } else if below(Level::Warning) {
+            Level::Warning
+        } else {
+            Level::Ok
+        }
+    }
+
+    /// Records a balance read at `ts` and returns the new level if it
+    /// changed.
+    pub fn observe(&mut self, ts: u64, balance: f64) -> Option<Level> {
+        let window = self
+            .thresholds
+            .runway_window
+            .unwrap_or(DEFAULT_RUNWAY_WINDOW)
+            * 1000;
+        self.samples.push_back((ts, balance));
+        while let Some((first, _)) = self.samples.front() {
+            if ts - first <= window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+
+        let level = self.classify(balance);
+        if level == self.level {
+            return None;
+        }
+        self.level = level;
+        Some(level)
+    }
+
+    /// Days until the balance runs out at the spend rate of the window.
+    /// Top ups are not counted as negative spend.
+    pub fn runway_days(&self) -> Option<f64> {
+        let (first, _) = self.samples.front()?;
+        let (last, balance) = self.samples.back()?;
+        if last <= first {
+            return None;
+        }
+        let spent: f64 = self
+            .samples
+            .iter()
+            .zip(self.samples.iter().skip(1))
+            .map(|((_, a), (_, b))| (a - b).max(0.0))
+            .sum();
+        if spent <= 0.0 {
+            return None;
+        }
+        let per_day = spent * DAY_MS / (last - first) as f64;
+        Some(balance / per_day)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 86400000;
+
+    fn monitor() -> Monitor {
+        Monitor::new(Thresholds {
+            warning: Some(10.0),
+            critical: Some(5.0),
+            hysteresis: Some(0.1),
+            runway_window: None,
+        })
+    }
+
+    #[test]
+    fn alarms_once_per_level() {
+        let mut m = monitor();
+        assert_eq!(m.observe(0, 12.0), None);
+        assert_eq!(m.observe(1, 9.0), Some(Level::Warning));
+        assert_eq!(m.observe(2, 8.0), None);
+        assert_eq!(m.observe(3, 4.0), Some(Level::Critical));
+        assert_eq!(m.observe(4, 3.0), None);
+        assert_eq!(m.level(), Level::Critical);
+    }
+
+    #[test]
+    fn rearms_only_above_the_margin() {
+        let mut m = monitor();
+        m.observe(0, 9.0);
+        // Back above the threshold but within 10% of it.
+        assert_eq!(m.observe(1, 10.5), None);
+        assert_eq!(m.observe(2, 9.5), None);
+        assert_eq!(m.observe(3, 11.0), Some(Level::Ok));
+        // Re-armed: dropping below the plain threshold alarms again.
+        assert_eq!(m.observe(4, 10.5), None);
+        assert_eq!(m.observe(5, 9.9), Some(Level::Warning));
+
+        m.observe(6, 4.0);
+        assert_eq!(m.observe(7, 5.4), None);
+        assert_eq!(m.observe(8, 5.6), Some(Level::Warning));
+    }
+
+    #[test]
+    fn runway_from_spend_rate() {
+        let mut m = monitor();
+        assert_eq!(m.runway_days(), None);
+        m.observe(0, 10.0);
+        assert_eq!(m.runway_days(), None);
+        m.observe(DAY / 2, 9.0);
+        m.observe(DAY, 8.0);
+        assert_eq!(m.runway_days(), Some(4.0));
+    }
+
+    #[test]
+    fn runway_ignores_top_ups() {
+        let mut m = monitor();
+        m.observe(0, 10.0);
+        m.observe(DAY / 2, 9.0);
+        m.observe(DAY / 2 + 1, 15.0);
+        m.observe(DAY, 14.0);
+        assert_eq!(m.runway_days(), Some(7.0));
+    }
+
+    #[test]
+    fn runway_window_drops_old_samples() {
+        let mut m = monitor();
+        m.observe(0, 100.0);
+        m.observe(DAY, 10.0);
+        m.observe(2 * DAY, 9.0);
+        // Only the last day counts: one coin a day.
+        assert_eq!(m.runway_days(), Some(9.0));
+
+        let mut m = monitor();
+        m.observe(0, 10.0);
+        m.observe(DAY, 10.0);
+        assert_eq!(m.runway_days(), None);
+    }
+}
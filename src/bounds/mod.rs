@@ -0,0 +1,176 @@
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::decimal::{Decimal, BPS};
+
+lazy_static! {
+    static ref VIOLATED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// Absolute limits a coin's price must respect before it is published.
+/// `max_change` is the allowed 24h move as a fraction, e.g. 0.5 for 50%.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bounds {
+    pub min_price: Option<Decimal>,
+    pub max_price: Option<Decimal>,
+    pub max_change: Option<Decimal>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Violation {
+    BelowMin(Decimal),
+    AboveMax(Decimal),
+    Change {
+        reference: Decimal,
+        change_bps: Decimal,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::BelowMin(min) => write!(f, "below min price {}", min),
+            Violation::AboveMax(max) => write!(f, "above max price {}", max),
+            Violation::Change {
+                reference,
+                change_bps,
+            } => write!(
+                f,
+                "moved {} bps from {} 24h ago",
+                change_bps.round_dp(2),
+                reference
+            ),
+        }
+    }
+}
+
+/// Checks `price` against the bounds. `reference` is the price published
+/// about 24h ago, if one is known.
+pub fn check(
+    price: Decimal,
+    bounds: &Bounds,
+    reference: Option<Decimal>,
+) -> Option<Violation> {
+    if let Some(min) = bounds.min_price {
+        if price < min {
+            return Some(Violation::BelowMin(min));
+        }
+    }
+    if let Some(max) = bounds.max_price {
+        if price > max {
+            return Some(Violation::AboveMax(max));
+        }
+    }
+    if let (Some(max_change), Some(reference)) = (bounds.max_change, reference)
+    {
+        if reference.is_zero() {
+            return None;
+        }
+        // A change too large to represent is a violation too.
+        let change = (price - reference).abs().checked_div(reference);
+        if !matches!(change, Some(change) if change <= max_change) {
+            let change_bps = change
+                .and_then(|change| change.checked_mul(BPS))
+                .unwrap_or(Decimal::MAX);
+            return Some(Violation::Change {
+                reference,
+                change_bps,
+            });
+        }
+    }
+    None
+}
+
+/// Tracks which coins are out of bounds so that an alarm is raised once per
+/// violation instead of every round. Returns true when `symbol` just entered
+/// the violated state.
+pub fn transition(symbol: &str, violated: bool) -> bool {
+    let mut state = VIOLATED.lock().unwrap();
+    if violated {
+        state.insert(symbol.to_owned())
+    } else {
+        state.remove(symbol);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decimal;
+
+    fn d(s: &str) -> Decimal {
+        decimal::parse(s).unwrap()
+    }
+
+    fn bounds() -> Bounds {
+        Bounds {
+            min_price: Some(d("0.5")),
+            max_price: Some(d("2")),
+            max_change: Some(d("0.1")),
+        }
+    }
+
+    #[test]
+    fn min_and_max() {
+        assert!(matches!(
+            check(d("0.4"), &bounds(), None),
+            Some(Violation::BelowMin(_))
+        ));
+        assert!(matches!(
+            check(d("2.1"), &bounds(), None),
+            Some(Violation::AboveMax(_))
+        ));
+        assert!(check(d("0.5"), &bounds(), None).is_none());
+        assert!(check(d("2"), &bounds(), None).is_none());
+    }
+
+    #[test]
+    fn change_from_reference() {
+        // 10% either way is allowed, more is not.
+        assert!(check(d("1.1"), &bounds(), Some(d("1"))).is_none());
+        assert!(check(d("0.9"), &bounds(), Some(d("1"))).is_none());
+        match check(d("1.2"), &bounds(), Some(d("1"))) {
+            Some(Violation::Change { change_bps, .. }) => {
+                assert_eq!(change_bps, d("2000"))
+            }
+            v => panic!("unexpected {:?}", v),
+        }
+        // No reference or a zero one skips the check.
+        assert!(check(d("1.9"), &bounds(), None).is_none());
+        assert!(check(d("1.9"), &bounds(), Some(Decimal::ZERO)).is_none());
+    }
+
+    #[test]
+    fn tiny_reference_is_a_violation() {
+        let bounds = Bounds {
+            min_price: None,
+            max_price: None,
+            max_change: Some(d("0.1")),
+        };
+        let reference = Decimal::new(1, 28);
+        match check(Decimal::MAX, &bounds, Some(reference)) {
+            Some(Violation::Change { change_bps, .. }) => {
+                assert_eq!(change_bps, Decimal::MAX)
+            }
+            v => panic!("unexpected {:?}", v),
+        }
+    }
+
+    #[test]
+    fn violation_alarms_once() {
+        let symbol = "BOUNDS_TEST";
+        assert!(transition(symbol, true));
+        // Still violated: no new alarm.
+        assert!(!transition(symbol, true));
+        assert!(!transition(symbol, true));
+        // Back in bounds, then out again alarms again.
+        assert!(!transition(symbol, false));
+        assert!(!transition(symbol, false));
+        assert!(transition(symbol, true));
+        transition(symbol, false);
+    }
+}
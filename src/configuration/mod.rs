@@ -1,16 +1,37 @@
 use anyhow::{Ok, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use zeroize::Zeroize;
+
+use crate::balance;
+use crate::bounds::Bounds;
+use crate::decimal::{Decimal, Rounding};
+use crate::sink::starknet;
+use crate::smoothing;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Configuration {
     pub interval: u64,
     pub coins: Vec<String>,
     pub decimals: Vec<u64>,
-    pub imitations: Option<HashMap<String, f64>>,
+    pub imitations: Option<HashMap<String, Decimal>>,
+    pub pegs: Option<HashMap<String, PegConfig>>,
+    pub derived: Option<HashMap<String, DerivedConfig>>,
+    pub smoothing: Option<HashMap<String, smoothing::Method>>,
+    /// Smoothing and bounds history in `data_dir`, history.json by
+    /// default. Only written when either is configured.
+    pub history_file: Option<String>,
+    /// Writable directory for the history, records, top up log and log
+    /// files. Defaults to the directory of the executable.
+    pub data_dir: Option<String>,
+    pub bounds: Option<HashMap<String, Bounds>>,
+    pub rounding: Option<Rounding>,
     pub package_id: String,
     pub oracle_cap: String,
     pub price_oracle: String,
+    /// Json pointer to the price vector in the fields of `price_oracle`,
+    /// used to read the stored prices back.
+    pub prices_path: Option<String>,
     pub smtp: String,
     pub port: u16,
     pub from: String,
@@ -21,7 +42,18 @@ pub struct Configuration {
     pub active: u8,
     pub diffs: HashMap<String, f64>,
     pub ratio: f64,
+    pub consensus: Option<ConsensusConfig>,
+    pub liquidity: Option<LiquidityConfig>,
+    pub recorder: Option<RecorderConfig>,
+    pub sinks: Option<Vec<SinkConfig>>,
+    pub dry_run: Option<DryRunConfig>,
+    pub gas_pool: Option<GasPoolConfig>,
+    pub top_up: Option<TopUpConfig>,
     pub balance: u64,
+    /// Per sink balance thresholds. Sinks without an entry warn below
+    /// `balance` base units of their coin, so evm and starknet sinks should
+    /// have one.
+    pub balance_alarms: Option<HashMap<String, balance::Thresholds>>,
     pub gas_budget: u64,
     pub enable_balance_alarm: bool,
     pub enable_price_alarm: bool,
@@ -45,26 +77,280 @@ pub struct Configuration {
     pub publickeys: Vec<String>,
     pub weights: Vec<u8>,
     pub threshold: u16,
+    pub cosigners: Option<Vec<CosignerConfig>>,
+    /// Seconds to wait for co-signers. Defaults to 10.
+    pub cosign_timeout: Option<u64>,
+    pub cosign: Option<CosignConfig>,
+    pub signer: Option<SignerConfig>,
+    pub keystore: Option<KeystoreConfig>,
+    /// Scheme of the feeder key: ed25519 (default), secp256k1 or secp256r1.
+    pub key_scheme: Option<String>,
+    /// Bip32 path a mnemonic is derived along. Defaults to the scheme's
+    /// first account.
+    pub derivation_path: Option<String>,
     pub gas: String,
     pub usdt_active: u8,
     pub rpcs: Vec<String>,
+    /// Sui chain identifier, e.g. 35834a8a for mainnet. Rpcs on another
+    /// chain are refused.
+    pub chain_id: Option<String>,
+    pub rpc_pool: Option<RpcPoolConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConsensusConfig {
+    pub primaries: Vec<String>,
+    pub min_sources: usize,
+    pub fallback: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PegConfig {
+    pub value: Option<Decimal>,
+    pub target: Option<String>,
+    pub band: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RateConfig {
+    pub url: Option<String>,
+    pub object: Option<String>,
+    pub path: String,
+    pub scale: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DerivedConfig {
+    pub formula: String,
+    pub rates: Option<HashMap<String, RateConfig>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LiquidityConfig {
+    pub half_life: u64,
+    pub max_weight: Decimal,
+    pub trust: Option<HashMap<String, Decimal>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecorderConfig {
+    /// Directory of the record files, relative to `data_dir`.
+    pub dir: String,
+    /// Rounds per file before rotating. Defaults to 1000.
+    pub rotate_rounds: Option<u64>,
+    /// Oldest files beyond this count are deleted.
+    pub keep_files: Option<usize>,
+}
+
+/// A publishing target. Without `sinks`, the top level package and oracle
+/// fields make up a single sui sink.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "chain", rename_all = "snake_case")]
+pub enum SinkConfig {
+    Sui(SuiSinkConfig),
+    Evm(EvmSinkConfig),
+    Starknet(StarknetSinkConfig),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SuiSinkConfig {
+    pub name: String,
+    pub package_id: String,
+    pub module: Option<String>,
+    pub function: Option<String>,
+    pub oracle_cap: String,
+    pub price_oracle: String,
+    pub gas_budget: Option<u64>,
+    /// Defaults to the reference gas price.
+    pub gas_price: Option<u64>,
+    /// Defaults to the top level `dry_run`.
+    pub dry_run: Option<DryRunConfig>,
+    /// Further oracle objects updated in the same transaction.
+    pub extra_oracles: Option<Vec<OracleObjects>>,
+    /// Json pointer to the price vector in the oracle object's fields.
+    pub prices_path: Option<String>,
+    /// Json pointer to the timestamp vector.
+    pub timestamps_path: Option<String>,
+    /// Read the oracle back after each update and fail on mismatches.
+    pub verify: Option<bool>,
+    /// Defaults to the top level `rpcs`.
+    pub rpcs: Option<Vec<String>>,
+}
+
+/// Background health checks of the sui rpcs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcPoolConfig {
+    /// Seconds between probes. Defaults to 30.
+    pub probe_interval: Option<u64>,
+    /// Seconds a probe may take. Defaults to 5.
+    pub probe_timeout: Option<u64>,
+    /// Checkpoints an rpc may trail the most advanced one. Defaults to 20.
+    pub max_lag: Option<u64>,
+}
+
+/// Gas coins kept for the sui feeder account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GasPoolConfig {
+    /// Number of coins split off the reserve.
+    pub size: usize,
+    /// Balance of each split coin, in mist.
+    pub coin_balance: u64,
+    /// Coins below this balance are merged into the reserve.
+    pub dust: u64,
+    /// Seconds between maintenance transactions.
+    pub maintain_interval: u64,
+}
+
+/// Refills the sui feeder account from a treasury, either a key that signs
+/// transfers or a faucet style endpoint. All sui sinks share one daily cap.
+/// Transfers to a multisig land as new coins, so a multisig is only topped
+/// up with `gas_pool` set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopUpConfig {
+    /// Balance in mist below which a top up is requested. Defaults to
+    /// `balance`.
+    pub threshold: Option<u64>,
+    /// Mist per top up.
+    pub amount: u64,
+    /// Most mist transferred in 24 hours.
+    pub daily_cap: u64,
+    /// Env var holding the treasury key, base64 as in the sui keystore.
+    pub key_env: Option<String>,
+    /// Posted `{recipient, amount}` instead of signing with a key.
+    pub url: Option<String>,
+    /// Where the transfers of the last day are kept. Defaults to
+    /// top_ups.json in `data_dir`.
+    pub log_file: Option<String>,
+}
+
+/// Simulate each sui update before sending it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DryRunConfig {
+    /// Added on top of the simulated gas. Defaults to 0.2.
+    pub margin: Option<f64>,
+    /// Meanings of the oracle's abort codes, for logs.
+    pub abort_codes: Option<HashMap<u64, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OracleObjects {
+    pub oracle_cap: String,
+    pub price_oracle: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EvmSinkConfig {
+    pub name: String,
+    pub rpc: String,
+    pub chain_id: u64,
+    pub contract: String,
+    /// Called as `function(uint8[],uint256[],uint64[])`. Defaults to
+    /// updatePrices.
+    pub function: Option<String>,
+    /// View `getter(uint8) returns (uint256)` used to read prices back.
+    pub getter: Option<String>,
+    /// Env var holding the hex private key. Defaults to EVM_KEY.
+    pub key_env: Option<String>,
+    /// Estimated per transaction when missing.
+    pub gas_limit: Option<u64>,
+    /// Priority fee in wei. Queried from the node when missing.
+    pub priority_fee: Option<u64>,
+    /// Cap on the max fee per gas in wei.
+    pub max_fee: Option<u64>,
+    /// Seconds before a pending transaction is replaced with higher fees.
+    pub stuck_after: Option<u64>,
+    /// Fee increase of a replacement, in percent. At least 10.
+    pub fee_bump: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StarknetSinkConfig {
+    pub name: String,
+    pub rpc: String,
+    /// Address of the account contract that signs and pays.
+    pub account: String,
+    pub contract: String,
+    /// Called with `(ids, prices, timestamps)` arrays. Defaults to
+    /// update_prices.
+    pub entrypoint: Option<String>,
+    /// View `getter(idx)` used to read prices back.
+    pub getter: Option<String>,
+    pub encoding: Option<starknet::Encoding>,
+    /// Env var holding the hex stark key. Defaults to STARKNET_KEY.
+    pub key_env: Option<String>,
+    /// Defaults to eth.
+    pub fee_token: Option<String>,
+    /// Applied to the fee estimate, in basis points. Defaults to 15000.
+    pub fee_multiplier_bps: Option<u64>,
+}
+
+/// A remote multisig member asked for partial signatures.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CosignerConfig {
+    pub name: String,
+    /// Http endpoint, or `unix:/path` for a local socket.
+    pub url: String,
+    /// Base64 public key, one of `publickeys`.
+    pub publickey: String,
+}
+
+/// Encrypted key file unlocked at startup when no key or mnemonic is given.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeystoreConfig {
+    /// Relative to the executable.
+    pub path: String,
+    /// File holding the passphrase. Prompted for when missing.
+    pub passphrase_file: Option<String>,
+}
+
+/// Signs with a key kept in another process instead of the keystore. No
+/// key or mnemonic is needed then.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignerConfig {
+    /// Http endpoint, or `unix:/path` for a local socket.
+    pub url: String,
+    /// Address of the signer's key.
+    pub address: String,
+    /// Seconds to wait for a signature. Defaults to 10.
+    pub timeout: Option<u64>,
+}
+
+/// `tinyd cosign`: a multisig member that signs only updates matching its
+/// own view of the prices.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CosignConfig {
+    /// Tcp address for http, or `unix:/path` for a local socket.
+    pub listen: String,
+    /// Env var holding the co-signer key, base64 as in the sui keystore.
+    /// Defaults to COSIGN_KEY.
+    pub key_env: Option<String>,
+    /// Allowed distance of a proposed price from the own one. Own prices
+    /// are not smoothed, so with `smoothing` on this must also cover the
+    /// lag of the feeders' ema or twap during fast moves.
+    pub tolerance_bps: f64,
+    /// Seconds a proposed timestamp may be off. Defaults to two intervals.
+    pub max_age: Option<u64>,
+    /// Defaults to the top level `package_id`.
+    pub package_id: Option<String>,
+    pub module: Option<String>,
+    pub function: Option<String>,
+    /// Defaults to `gas_budget`.
+    pub max_gas_budget: Option<u64>,
 }
 
 pub struct SuiKey {
     pub key: String,
     pub mnemonic: String,
+    pub scheme: String,
+    pub derivation_path: String,
 }
 
-pub fn get_sui_config_path() -> Option<String> {
-    let current_path = std::env::current_exe().ok()?;
-    let parent_path = current_path.parent().unwrap();
-
-    let mut config_path = parent_path.to_path_buf();
-    config_path.push(r"sui_config");
-    config_path.push(r"client.yaml");
-
-    let path = config_path.into_os_string().into_string().unwrap();
-    Some(path)
+impl SuiKey {
+    /// Wipes the secrets once the signers hold them.
+    pub fn clear(&mut self) {
+        self.key.zeroize();
+        self.mnemonic.zeroize();
+    }
 }
 
 pub fn get_log_path(logfile: &str) -> Option<String> {
@@ -78,12 +364,37 @@ pub fn get_log_path(logfile: &str) -> Option<String> {
     Some(path)
 }
 
+/// Resolves `file` against `data_dir`, or the directory of the executable
+/// without one. Absolute paths are kept as they are.
+pub fn get_data_path(data_dir: Option<&str>, file: &str) -> Option<String> {
+    let mut data_path = match data_dir {
+        Some(dir) => std::path::PathBuf::from(dir),
+        None => {
+            let current_path = std::env::current_exe().ok()?;
+            current_path.parent().unwrap().to_path_buf()
+        }
+    };
+    data_path.push(file);
+
+    let path = data_path.into_os_string().into_string().unwrap();
+    Some(path)
+}
+
+/// Overrides the path of tiny.yaml, e.g. to replay against a candidate config.
+pub const CONFIG_ENV: &str = "TINY_CONFIG";
+
 pub fn read_configuration() -> Result<Configuration> {
-    let current_path = std::env::current_exe()?;
-    let parent_path = current_path.parent().unwrap();
+    let config_path = match std::env::var(CONFIG_ENV) {
+        std::result::Result::Ok(path) => std::path::PathBuf::from(path),
+        Err(_) => {
+            let current_path = std::env::current_exe()?;
+            let parent_path = current_path.parent().unwrap();
 
-    let mut config_path = parent_path.to_path_buf();
-    config_path.push(r"tiny.yaml");
+            let mut config_path = parent_path.to_path_buf();
+            config_path.push(r"tiny.yaml");
+            config_path
+        }
+    };
 
     let f = std::fs::File::open(config_path)?;
     let cfg: Configuration =
@@ -0,0 +1,201 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use shared_crypto::intent::{Intent, IntentMessage};
+use std::time::Duration;
+use sui_types::base_types::SuiAddress;
+use sui_types::crypto::{
+    EncodeDecodeBase64, PublicKey, Signature, SuiSignature,
+};
+use sui_types::transaction::TransactionData;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::task::JoinSet;
+
+use crate::configuration::CosignerConfig;
+use crate::request;
+
+pub mod policy;
+pub mod server;
+
+pub const DEFAULT_TIMEOUT: u64 = 10;
+pub const DEFAULT_KEY_ENV: &str = "COSIGN_KEY";
+/// Co-signer urls with this prefix are unix sockets speaking one json line
+/// each way.
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// Asks a co-signer to sign the transaction. It checks the update calls in
+/// it against its own policy first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CosignRequest {
+    pub tx_data: TransactionData,
+}
+
+/// Either a base64 signature or the reason the co-signer refused.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CosignResponse {
+    pub signature: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cosigner {
+    pub name: String,
+    pub url: String,
+    pub publickey: PublicKey,
+}
+
+impl Cosigner {
+    pub fn new(cfg: &CosignerConfig) -> Result<Cosigner> {
+        let publickey = PublicKey::decode_base64(&cfg.publickey)
+            .map_err(|_| anyhow!("{}: invalid public key", cfg.name))?;
+        Ok(Cosigner {
+            name: cfg.name.clone(),
+            url: cfg.url.clone(),
+            publickey,
+        })
+    }
+}
+
+/// Multisig members as decoded public keys with their weights.
+pub fn members(
+    publickeys: &[String],
+    weights: &[u8],
+) -> Result<Vec<(PublicKey, u8)>> {
+    if publickeys.len() != weights.len() {
+        return Err(anyhow!("publickeys and weights differ in length"));
+    }
+    let mut members = Vec::new();
+    for (key, weight) in publickeys.iter().zip(weights.iter()) {
+        let pk = PublicKey::decode_base64(key)
+            .map_err(|_| anyhow!("invalid public key {}", key))?;
+        members.push((pk, *weight));
+    }
+    Ok(members)
+}
+
+fn weight_of(members: &[(PublicKey, u8)], signature: &Signature) -> u16 {
+    members
+        .iter()
+        .find(|(pk, _)| pk.as_ref() == signature.public_key_bytes())
+        .map_or(0, |(_, weight)| *weight as u16)
+}
+
+async fn request_unix(path: &str, request: &CosignRequest) -> Result<String> {
+    let mut stream = UnixStream::connect(path).await?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes()).await?;
+
+    let mut reader = BufReader::new(stream);
+    let mut response = String::new();
+    reader.read_line(&mut response).await?;
+    let response: CosignResponse = serde_json::from_str(&response)?;
+    match (response.signature, response.error) {
+        (Some(signature), _) => Ok(signature),
+        (None, error) => Err(anyhow!(error.unwrap_or_default())),
+    }
+}
+
+async fn request_http(url: &String, request: &CosignRequest) -> Result<String> {
+    let response: CosignResponse = request::post(url, request).await?;
+    match (response.signature, response.error) {
+        (Some(signature), _) => Ok(signature),
+        (None, error) => Err(anyhow!(error.unwrap_or_default())),
+    }
+}
+
+/// Sends the request to `url`, over a unix socket for `unix:` urls, and
+/// returns the signature checked to be `author`'s over the transaction.
+pub async fn request_signature(
+    url: &String,
+    request: &CosignRequest,
+    author: SuiAddress,
+) -> Result<Signature> {
+    let encoded = match url.strip_prefix(UNIX_PREFIX) {
+        Some(path) => request_unix(path, request).await?,
+        None => request_http(url, request).await?,
+    };
+    let signature = Signature::decode_base64(&encoded)
+        .map_err(|_| anyhow!("invalid signature"))?;
+
+    let message =
+        IntentMessage::new(Intent::sui_transaction(), &request.tx_data);
+    signature
+        .verify_secure(&message, author, signature.scheme())
+        .map_err(|e| anyhow!("signature rejected: {}", e))?;
+    Ok(signature)
+}
+
+/// Asks the co-signers in parallel and returns the local signature plus
+/// enough of theirs to reach `threshold`. Co-signers that fail, refuse, miss
+/// the timeout or sign with a key already counted are skipped.
+pub async fn collect_signatures(
+    tx_data: &TransactionData,
+    local: Signature,
+    members: &[(PublicKey, u8)],
+    threshold: u16,
+    cosigners: &[Cosigner],
+    timeout: Duration,
+) -> Result<Vec<Signature>> {
+    let mut weight = weight_of(members, &local);
+    let mut signed_by = vec![local.public_key_bytes().to_vec()];
+    let mut signatures = vec![local];
+    if weight >= threshold {
+        return Ok(signatures);
+    }
+
+    let mut tasks = JoinSet::new();
+    for cosigner in cosigners.iter().cloned() {
+        let tx_data = tx_data.clone();
+        tasks.spawn(async move {
+            let request = CosignRequest { tx_data };
+            let author = SuiAddress::from(&cosigner.publickey);
+            let result = tokio::time::timeout(
+                timeout,
+                request_signature(&cosigner.url, &request, author),
+            )
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("timed out")));
+            (cosigner.name, result)
+        });
+    }
+
+    let mut failures = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        let (name, result) = match joined {
+            Ok(joined) => joined,
+            Err(e) => {
+                failures.push(e.to_string());
+                continue;
+            }
+        };
+        match result {
+            Ok(signature) => {
+                let signed = weight_of(members, &signature);
+                if signed == 0 {
+                    failures.push(format!("{}: not a member", name));
+                    continue;
+                }
+                let key = signature.public_key_bytes().to_vec();
+                if signed_by.contains(&key) {
+                    failures.push(format!("{}: key already signed", name));
+                    continue;
+                }
+                signed_by.push(key);
+                weight += signed;
+                signatures.push(signature);
+                if weight >= threshold {
+                    // Dropping the set aborts the co-signers still pending.
+                    return Ok(signatures);
+                }
+            }
+            Err(e) => failures.push(format!("{}: {}", name, e)),
+        }
+    }
+    Err(anyhow!(
+        "signatures weigh {} of threshold {} ({})",
+        weight,
+        threshold,
+        failures.join(", ")
+    ))
+}
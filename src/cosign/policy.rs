@@ -0,0 +1,155 @@
+use anyhow::{anyhow, Result};
+use move_core_types::u256::U256;
+use sui_sdk::types::base_types::SuiAddress;
+use sui_types::base_types::ObjectID;
+use sui_types::transaction::{
+    Argument, CallArg, Command, ProgrammableTransaction, TransactionData,
+    TransactionDataAPI, TransactionKind,
+};
+
+/// The arguments of one proposed `update_token_price_batch` call.
+#[derive(Debug, Clone)]
+pub struct Update {
+    pub coin_idxs: Vec<u8>,
+    pub prices: Vec<u128>,
+    pub timestamps: Vec<u64>,
+}
+
+/// What a co-signer agrees to sign: calls to the oracle's update function,
+/// plus gas coin merges and splits that stay with the sender.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub package: ObjectID,
+    pub module: String,
+    pub function: String,
+    pub max_gas_budget: u64,
+    /// Milliseconds a proposed timestamp may be away from now.
+    pub max_age: u64,
+    pub tolerance_bps: f64,
+}
+
+fn pure<'a>(
+    pt: &'a ProgrammableTransaction,
+    arg: Option<&Argument>,
+) -> Result<&'a [u8]> {
+    let idx = match arg {
+        Some(Argument::Input(idx)) => *idx as usize,
+        _ => return Err(anyhow!("argument is not an input")),
+    };
+    match pt.inputs.get(idx) {
+        Some(CallArg::Pure(bytes)) => Ok(bytes),
+        _ => Err(anyhow!("input {} is not pure", idx)),
+    }
+}
+
+fn decode_update(
+    pt: &ProgrammableTransaction,
+    args: &[Argument],
+) -> Result<Update> {
+    let coin_idxs: Vec<u8> = bcs::from_bytes(pure(pt, args.get(2))?)?;
+    let prices: Vec<U256> = bcs::from_bytes(pure(pt, args.get(3))?)?;
+    let timestamps: Vec<u64> = bcs::from_bytes(pure(pt, args.get(4))?)?;
+    let prices = prices
+        .into_iter()
+        .map(|p| u128::try_from(p).map_err(|_| anyhow!("price over u128")))
+        .collect::<Result<Vec<u128>>>()?;
+    if prices.len() != coin_idxs.len() || timestamps.len() != coin_idxs.len() {
+        return Err(anyhow!("argument lengths differ"));
+    }
+    Ok(Update {
+        coin_idxs,
+        prices,
+        timestamps,
+    })
+}
+
+impl Policy {
+    /// Refuses anything but oracle updates and gas upkeep, and returns the
+    /// proposed updates.
+    pub fn check_calls(
+        &self,
+        tx_data: &TransactionData,
+        now: u64,
+    ) -> Result<Vec<Update>> {
+        if tx_data.gas_budget() > self.max_gas_budget {
+            return Err(anyhow!(
+                "gas budget {} over {}",
+                tx_data.gas_budget(),
+                self.max_gas_budget
+            ));
+        }
+        let pt = match tx_data.kind() {
+            TransactionKind::ProgrammableTransaction(pt) => pt,
+            _ => return Err(anyhow!("not a programmable transaction")),
+        };
+
+        let mut updates = Vec::new();
+        for command in pt.commands.iter() {
+            match command {
+                Command::MoveCall(call) => {
+                    if call.package != self.package
+                        || call.module.as_str() != self.module
+                        || call.function.as_str() != self.function
+                    {
+                        return Err(anyhow!(
+                            "call to {}::{}::{}",
+                            call.package,
+                            call.module,
+                            call.function
+                        ));
+                    }
+                    updates.push(decode_update(pt, &call.arguments)?);
+                }
+                Command::MergeCoins(Argument::GasCoin, _)
+                | Command::SplitCoins(Argument::GasCoin, _) => {}
+                Command::TransferObjects(_, recipient) => {
+                    let recipient: SuiAddress =
+                        bcs::from_bytes(pure(pt, Some(recipient))?)?;
+                    if recipient != tx_data.sender() {
+                        return Err(anyhow!("transfer to {}", recipient));
+                    }
+                }
+                _ => return Err(anyhow!("command {:?} not allowed", command)),
+            }
+        }
+
+        for update in updates.iter() {
+            for ts in update.timestamps.iter() {
+                if now.abs_diff(*ts) > self.max_age {
+                    return Err(anyhow!(
+                        "timestamp {} too far from {}",
+                        ts,
+                        now
+                    ));
+                }
+            }
+        }
+        Ok(updates)
+    }
+
+    /// Compares the proposed prices with the co-signer's own scaled prices,
+    /// indexed by coin.
+    pub fn check_prices(
+        &self,
+        update: &Update,
+        own: &[Option<u128>],
+    ) -> Result<()> {
+        for (idx, price) in update.coin_idxs.iter().zip(update.prices.iter()) {
+            let own = match own.get(*idx as usize) {
+                Some(Some(own)) if *own > 0 => *own as f64,
+                _ => return Err(anyhow!("no own price for coin {}", idx)),
+            };
+            let deviation = (*price as f64 - own).abs() * 10000.0 / own;
+            if deviation > self.tolerance_bps {
+                return Err(anyhow!(
+                    "coin {} price {} is {:.2} bps from {}",
+                    idx,
+                    price,
+                    deviation,
+                    own
+                ));
+            }
+        }
+        Ok(())
+    }
+}
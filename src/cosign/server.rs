@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Result};
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+
+use super::{CosignRequest, CosignResponse, UNIX_PREFIX};
+
+/// Largest request accepted, headers and body each. A transaction is a few
+/// KiB at most.
+pub const MAX_REQUEST: usize = 256 * 1024;
+/// Seconds a peer has to send its request.
+pub const READ_TIMEOUT: u64 = 5;
+
+/// Takes co-signing requests as http posts on a tcp address, or as json
+/// lines on a unix socket.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+pub enum Connection {
+    Http(BufReader<TcpStream>),
+    Line(BufReader<UnixStream>),
+}
+
+impl Listener {
+    pub async fn bind(listen: &str) -> Result<Listener> {
+        match listen.strip_prefix(UNIX_PREFIX) {
+            Some(path) => {
+                // A socket left over from a previous run blocks the bind.
+                let _ = std::fs::remove_file(path);
+                Ok(Listener::Unix(UnixListener::bind(path)?))
+            }
+            None => Ok(Listener::Tcp(TcpListener::bind(listen).await?)),
+        }
+    }
+
+    pub async fn accept(&self) -> Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok(Connection::Http(BufReader::new(stream)))
+            }
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok(Connection::Line(BufReader::new(stream)))
+            }
+        }
+    }
+}
+
+/// Reads one line of at most `MAX_REQUEST` bytes.
+async fn read_line<R>(reader: &mut BufReader<R>) -> Result<String>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut line = String::new();
+    let mut limited = reader.take(MAX_REQUEST as u64);
+    if limited.read_line(&mut line).await? == 0 {
+        return Err(anyhow!("connection closed"));
+    }
+    if !line.ends_with('\n') && limited.limit() == 0 {
+        return Err(anyhow!("request over {} bytes", MAX_REQUEST));
+    }
+    Ok(line)
+}
+
+async fn read_http_body(reader: &mut BufReader<TcpStream>) -> Result<Vec<u8>> {
+    let mut length = None;
+    let mut head = 0;
+    loop {
+        let line = read_line(reader).await?;
+        head += line.len();
+        if head > MAX_REQUEST {
+            return Err(anyhow!("headers over {} bytes", MAX_REQUEST));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+
+    let length = length.ok_or(anyhow!("no content length"))?;
+    if length > MAX_REQUEST {
+        return Err(anyhow!("body of {} bytes over {}", length, MAX_REQUEST));
+    }
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await?;
+    Ok(body)
+}
+
+impl Connection {
+    /// Fails on requests over `MAX_REQUEST` or slower than `READ_TIMEOUT`.
+    pub async fn read_request(&mut self) -> Result<CosignRequest> {
+        let timeout = std::time::Duration::from_secs(READ_TIMEOUT);
+        tokio::time::timeout(timeout, self.read())
+            .await
+            .map_err(|_| anyhow!("no request within {}s", READ_TIMEOUT))?
+    }
+
+    async fn read(&mut self) -> Result<CosignRequest> {
+        match self {
+            Connection::Http(reader) => {
+                let body = read_http_body(reader).await?;
+                Ok(serde_json::from_slice(&body)?)
+            }
+            Connection::Line(reader) => {
+                let line = read_line(reader).await?;
+                Ok(serde_json::from_str(&line)?)
+            }
+        }
+    }
+
+    pub async fn respond(&mut self, response: &CosignResponse) -> Result<()> {
+        let body = serde_json::to_string(response)?;
+        match self {
+            Connection::Http(reader) => {
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let stream = reader.get_mut();
+                stream.write_all(head.as_bytes()).await?;
+                stream.write_all(body.as_bytes()).await?;
+                stream.shutdown().await?;
+            }
+            Connection::Line(reader) => {
+                let stream = reader.get_mut();
+                stream.write_all(body.as_bytes()).await?;
+                stream.write_all(b"\n").await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use thiserror::Error;
+
+pub use rust_decimal::Decimal;
+
+/// One in basis points.
+pub const BPS: Decimal = Decimal::from_parts(10000, 0, 0, false, 0);
+
+#[derive(Error, Debug)]
+pub enum DecimalError {
+    #[error("invalid number {0}")]
+    InvalidNumber(String),
+    #[error("negative value {0}")]
+    Negative(Decimal),
+    #[error("{0} with {1} decimals overflows u128")]
+    Overflow(Decimal, u64),
+}
+
+/// How to round a price when it has more fractional digits than the coin's
+/// on-chain decimals.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    #[default]
+    Down,
+    Up,
+    HalfUp,
+    HalfEven,
+}
+
+/// Parses an exchange price or volume string. Plain and scientific notation
+/// ("1.2e-5") are both accepted.
+pub fn parse(s: &str) -> Result<Decimal> {
+    let s = s.trim();
+    match Decimal::from_str(s) {
+        Ok(d) => Ok(d),
+        Err(_) => match Decimal::from_scientific(s) {
+            Ok(d) => Ok(d),
+            Err(_) => Err(DecimalError::InvalidNumber(s.to_owned()).into()),
+        },
+    }
+}
+
+/// Converts a number that an exchange only gives as a json float. The
+/// shortest representation is used, not the exact binary expansion.
+pub fn from_f64(v: f64) -> Result<Decimal> {
+    if !v.is_finite() {
+        return Err(DecimalError::InvalidNumber(v.to_string()).into());
+    }
+    parse(&v.to_string())
+}
+
+fn pow10(exp: u32) -> Option<u128> {
+    10u128.checked_pow(exp)
+}
+
+/// Scales `value` to an integer with `decimals` fractional digits, as stored
+/// by the oracle contract.
+pub fn to_scaled(
+    value: Decimal,
+    decimals: u64,
+    rounding: Rounding,
+) -> Result<u128> {
+    if value.is_sign_negative() && !value.is_zero() {
+        return Err(DecimalError::Negative(value).into());
+    }
+
+    let mantissa = value.mantissa() as u128;
+    let scale = value.scale() as u64;
+
+    if decimals >= scale {
+        let factor = u32::try_from(decimals - scale)
+            .ok()
+            .and_then(pow10)
+            .ok_or(DecimalError::Overflow(value, decimals))?;
+        return mantissa
+            .checked_mul(factor)
+            .ok_or(DecimalError::Overflow(value, decimals).into());
+    }
+
+    let divisor = pow10((scale - decimals) as u32)
+        .ok_or(DecimalError::Overflow(value, decimals))?;
+    let quotient = mantissa / divisor;
+    let remainder = mantissa % divisor;
+    let half = divisor - remainder;
+
+    let round_up = match rounding {
+        Rounding::Down => false,
+        Rounding::Up => remainder > 0,
+        Rounding::HalfUp => remainder >= half,
+        Rounding::HalfEven => {
+            remainder > half || (remainder == half && quotient % 2 == 1)
+        }
+    };
+
+    if round_up {
+        return quotient
+            .checked_add(1)
+            .ok_or(DecimalError::Overflow(value, decimals).into());
+    }
+    Ok(quotient)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        parse(s).unwrap()
+    }
+
+    fn scaled(s: &str, decimals: u64, rounding: Rounding) -> u128 {
+        to_scaled(d(s), decimals, rounding).unwrap()
+    }
+
+    fn overflows(value: Decimal, decimals: u64) -> bool {
+        match to_scaled(value, decimals, Rounding::Down) {
+            Err(e) => matches!(
+                e.downcast_ref::<DecimalError>(),
+                Some(DecimalError::Overflow(..))
+            ),
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn half_up_rounds_ties_up() {
+        assert_eq!(scaled("1.25", 1, Rounding::HalfUp), 13);
+        assert_eq!(scaled("1.35", 1, Rounding::HalfUp), 14);
+        assert_eq!(scaled("1.249", 1, Rounding::HalfUp), 12);
+        assert_eq!(scaled("0.5", 0, Rounding::HalfUp), 1);
+    }
+
+    #[test]
+    fn half_even_rounds_ties_to_even() {
+        // Ties go down to an even digit and up to one.
+        assert_eq!(scaled("1.25", 1, Rounding::HalfEven), 12);
+        assert_eq!(scaled("1.35", 1, Rounding::HalfEven), 14);
+        assert_eq!(scaled("0.5", 0, Rounding::HalfEven), 0);
+        assert_eq!(scaled("1.5", 0, Rounding::HalfEven), 2);
+        // Off a tie it rounds to nearest either way.
+        assert_eq!(scaled("1.2501", 1, Rounding::HalfEven), 13);
+        assert_eq!(scaled("1.3499", 1, Rounding::HalfEven), 13);
+    }
+
+    #[test]
+    fn down_and_up_ignore_ties() {
+        assert_eq!(scaled("1.25", 1, Rounding::Down), 12);
+        assert_eq!(scaled("1.29", 1, Rounding::Down), 12);
+        assert_eq!(scaled("1.21", 1, Rounding::Up), 13);
+        assert_eq!(scaled("1.2", 1, Rounding::Up), 12);
+        assert_eq!(scaled("1.20000", 1, Rounding::Up), 12);
+    }
+
+    #[test]
+    fn max_precision_inputs() {
+        let tiny = "0.0000000000000000000000000001";
+        assert_eq!(d(tiny).scale(), 28);
+        assert_eq!(scaled(tiny, 9, Rounding::Down), 0);
+        assert_eq!(scaled(tiny, 9, Rounding::HalfUp), 0);
+        assert_eq!(scaled(tiny, 9, Rounding::Up), 1);
+        assert_eq!(scaled(tiny, 28, Rounding::Down), 1);
+        assert_eq!(scaled(tiny, 30, Rounding::Down), 100);
+
+        let max = to_scaled(Decimal::MAX, 9, Rounding::Down).unwrap();
+        assert_eq!(max, Decimal::MAX.mantissa() as u128 * 1_000_000_000);
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        assert!(overflows(Decimal::MAX, 10));
+        assert!(overflows(d("1"), 39));
+        assert!(overflows(d("1"), u64::MAX));
+        assert!(!overflows(d("1"), 38));
+    }
+
+    #[test]
+    fn negative_is_an_error() {
+        assert!(to_scaled(d("-0.01"), 2, Rounding::Down).is_err());
+        assert_eq!(scaled("-0", 2, Rounding::Down), 0);
+    }
+
+    #[test]
+    fn parses_plain_and_scientific() {
+        assert_eq!(d(" 42.10 "), Decimal::new(4210, 2));
+        assert_eq!(d("1.2e-5"), Decimal::new(12, 6));
+        assert!(parse("1,5").is_err());
+        assert!(from_f64(f64::NAN).is_err());
+        assert_eq!(from_f64(0.1).unwrap(), Decimal::new(1, 1));
+    }
+}
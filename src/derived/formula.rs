@@ -0,0 +1,257 @@
+use anyhow::{Ok, Result};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+use crate::decimal::{self, Decimal};
+
+#[derive(Error, Debug)]
+pub enum FormulaError {
+    #[error("unexpected character {0}")]
+    UnexpectedChar(char),
+    #[error("unexpected end of formula")]
+    UnexpectedEnd,
+    #[error("unexpected token {0:?}")]
+    UnexpectedToken(Token),
+    #[error("unknown input {0}")]
+    UnknownInput(String),
+    #[error("divide by zero")]
+    DivideByZero,
+    #[error("arithmetic overflow")]
+    Overflow,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Number(Decimal),
+    Ident(String),
+    Op(char),
+    LParen,
+    RParen,
+}
+
+fn tokenize(formula: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = formula.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_digit() || chars[i] == '.')
+            {
+                i += 1;
+            }
+            let s: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(decimal::parse(&s)?));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if "+-*/".contains(c) {
+            tokens.push(Token::Op(c));
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else {
+            return Err(FormulaError::UnexpectedChar(c).into());
+        }
+    }
+    Ok(tokens)
+}
+
+/// Returns the identifiers a formula refers to, in order of appearance.
+pub fn inputs(formula: &str) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for t in tokenize(formula)? {
+        if let Token::Ident(name) = t {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    Ok(names)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    values: &'a BTreeMap<String, Decimal>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t.ok_or(FormulaError::UnexpectedEnd.into())
+    }
+
+    fn expr(&mut self) -> Result<Decimal> {
+        let mut v = self.term()?;
+        while let Some(Token::Op(op)) = self.peek().cloned() {
+            if op != '+' && op != '-' {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.term()?;
+            v = match op {
+                '+' => v.checked_add(rhs),
+                _ => v.checked_sub(rhs),
+            }
+            .ok_or(FormulaError::Overflow)?;
+        }
+        Ok(v)
+    }
+
+    fn term(&mut self) -> Result<Decimal> {
+        let mut v = self.factor()?;
+        while let Some(Token::Op(op)) = self.peek().cloned() {
+            if op != '*' && op != '/' {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.factor()?;
+            v = match op {
+                '*' => v.checked_mul(rhs).ok_or(FormulaError::Overflow)?,
+                _ => {
+                    if rhs.is_zero() {
+                        return Err(FormulaError::DivideByZero.into());
+                    }
+                    v.checked_div(rhs).ok_or(FormulaError::Overflow)?
+                }
+            };
+        }
+        Ok(v)
+    }
+
+    fn factor(&mut self) -> Result<Decimal> {
+        match self.next()? {
+            Token::Number(n) => Ok(n),
+            Token::Ident(name) => match self.values.get(&name) {
+                Some(v) => Ok(*v),
+                None => Err(FormulaError::UnknownInput(name).into()),
+            },
+            Token::Op('-') => Ok(-self.factor()?),
+            Token::LParen => {
+                let v = self.expr()?;
+                match self.next()? {
+                    Token::RParen => Ok(v),
+                    t => Err(FormulaError::UnexpectedToken(t).into()),
+                }
+            }
+            t => Err(FormulaError::UnexpectedToken(t).into()),
+        }
+    }
+}
+
+/// Evaluates an arithmetic formula (`+ - * /`, parentheses) over named
+/// inputs.
+pub fn evaluate(
+    formula: &str,
+    values: &BTreeMap<String, Decimal>,
+) -> Result<Decimal> {
+    let mut parser = Parser {
+        tokens: tokenize(formula)?,
+        pos: 0,
+        values,
+    };
+    let v = parser.expr()?;
+    if let Some(t) = parser.peek() {
+        return Err(FormulaError::UnexpectedToken(t.clone()).into());
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        decimal::parse(s).unwrap()
+    }
+
+    fn eval(formula: &str) -> Result<Decimal> {
+        let values = BTreeMap::from([
+            ("a".to_owned(), d("2")),
+            ("b".to_owned(), d("3")),
+            ("zero".to_owned(), Decimal::ZERO),
+        ]);
+        evaluate(formula, &values)
+    }
+
+    fn error(formula: &str) -> FormulaError {
+        let e = eval(formula).unwrap_err();
+        match e.downcast::<FormulaError>() {
+            std::result::Result::Ok(e) => e,
+            Err(e) => panic!("{}: not a formula error: {}", formula, e),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), d("7"));
+        assert_eq!(eval("a + b * a").unwrap(), d("8"));
+        assert_eq!(eval("8 / 4 / 2").unwrap(), d("1"));
+        assert_eq!(eval("10 - 4 - 3").unwrap(), d("3"));
+        assert_eq!(eval("a * b - 1.5").unwrap(), d("4.5"));
+    }
+
+    #[test]
+    fn parentheses() {
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), d("9"));
+        assert_eq!(eval("a * (b - (1 + 1))").unwrap(), d("2"));
+        assert_eq!(eval("((a))").unwrap(), d("2"));
+    }
+
+    #[test]
+    fn unary_minus() {
+        assert_eq!(eval("-a").unwrap(), d("-2"));
+        assert_eq!(eval("-a * b").unwrap(), d("-6"));
+        assert_eq!(eval("b - -a").unwrap(), d("5"));
+        assert_eq!(eval("-(a + b)").unwrap(), d("-5"));
+    }
+
+    #[test]
+    fn divide_by_zero() {
+        assert!(matches!(error("a / zero"), FormulaError::DivideByZero));
+        assert!(matches!(error("1 / (a - 2)"), FormulaError::DivideByZero));
+    }
+
+    #[test]
+    fn unknown_input() {
+        assert!(matches!(
+            error("a + c"),
+            FormulaError::UnknownInput(name) if name == "c"
+        ));
+    }
+
+    #[test]
+    fn bad_syntax() {
+        assert!(matches!(error("a b"), FormulaError::UnexpectedToken(_)));
+        assert!(matches!(error("(a + b"), FormulaError::UnexpectedEnd));
+        assert!(matches!(error("a + b)"), FormulaError::UnexpectedToken(_)));
+        assert!(matches!(error("a +"), FormulaError::UnexpectedEnd));
+        assert!(matches!(error("* a"), FormulaError::UnexpectedToken(_)));
+        assert!(matches!(error("a % b"), FormulaError::UnexpectedChar('%')));
+        assert!(matches!(error(""), FormulaError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn inputs_in_order() {
+        assert_eq!(inputs("b * (a + b) - 1").unwrap(), vec!["b", "a"]);
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Ok, Result};
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use sui_json_rpc_types::{SuiObjectDataOptions, SuiParsedData};
+use sui_sdk::{SuiClient, SuiClientBuilder};
+use sui_types::base_types::ObjectID;
+use thiserror::Error;
+
+use crate::configuration::{DerivedConfig, RateConfig};
+use crate::decimal::{self, Decimal};
+use crate::request;
+
+pub mod formula;
+
+lazy_static! {
+    /// One client per rpc, built on its first object rate.
+    static ref CLIENTS: Mutex<HashMap<String, SuiClient>> =
+        Mutex::new(HashMap::new());
+}
+
+/// An object rate failed at the rpc rather than at the object, so another
+/// rpc may do better.
+#[derive(Debug, Error)]
+#[error("rpc {rpc}: {reason}")]
+pub struct RpcError {
+    pub rpc: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    pub price: Decimal,
+    pub inputs: BTreeMap<String, Decimal>,
+}
+
+fn json_to_decimal(v: &Value) -> Result<Decimal> {
+    match v {
+        Value::String(s) => decimal::parse(s),
+        Value::Number(n) => decimal::parse(&n.to_string()),
+        _ => Err(anyhow!("rate value {} is not a number", v)),
+    }
+}
+
+async fn get_client(rpc: &str) -> Result<SuiClient> {
+    if let Some(client) = CLIENTS.lock().unwrap().get(rpc) {
+        return Ok(client.clone());
+    }
+    let client = SuiClientBuilder::default().build(rpc).await?;
+    CLIENTS
+        .lock()
+        .unwrap()
+        .insert(rpc.to_owned(), client.clone());
+    Ok(client)
+}
+
+async fn fetch_object_fields(rpc: &str, object_id: &str) -> Result<Value> {
+    let id = ObjectID::from_hex_literal(object_id)?;
+    let rpc_error = |e: &dyn std::fmt::Display| RpcError {
+        rpc: rpc.to_owned(),
+        reason: e.to_string(),
+    };
+    let client = get_client(rpc).await.map_err(|e| rpc_error(&e))?;
+    let response = client
+        .read_api()
+        .get_object_with_options(id, SuiObjectDataOptions::new().with_content())
+        .await
+        .map_err(|e| rpc_error(&e))?;
+    let data = response
+        .data
+        .ok_or(anyhow!("object {} not found", object_id))?;
+    match data.content {
+        Some(SuiParsedData::MoveObject(object)) => {
+            Ok(object.fields.to_json_value())
+        }
+        _ => Err(anyhow!("object {} has no move content", object_id)),
+    }
+}
+
+/// Reads one external rate, either from a json http endpoint or from the
+/// fields of an on-chain object. `path` is a json pointer into the response.
+pub async fn fetch_rate(
+    rate: &RateConfig,
+    rpc: Option<&str>,
+) -> Result<Decimal> {
+    let document: Value = match (&rate.url, &rate.object) {
+        (Some(url), _) => request::request(url).await?,
+        (None, Some(object_id)) => {
+            let rpc = rpc.ok_or(anyhow!("no sui rpc for object rates"))?;
+            fetch_object_fields(rpc, object_id).await?
+        }
+        (None, None) => return Err(anyhow!("rate needs a url or an object")),
+    };
+
+    let raw = document
+        .pointer(&rate.path)
+        .ok_or(anyhow!("rate path {} not found", rate.path))?;
+    let mut value = json_to_decimal(raw)?;
+    if let Some(scale) = rate.scale {
+        value.set_scale(value.scale() + scale)?;
+    }
+    Ok(value)
+}
+
+/// Evaluates a derived coin once the base coins are aggregated. Rate inputs
+/// shadow coin symbols of the same name. An rpc failure can be told apart
+/// by downcasting to `RpcError`.
+pub async fn evaluate(
+    cfg: &DerivedConfig,
+    coins: &Vec<String>,
+    prices: &Vec<Decimal>,
+    rpc: Option<&str>,
+) -> Result<Evaluation> {
+    let mut inputs = BTreeMap::new();
+    for name in formula::inputs(&cfg.formula)? {
+        let rate = cfg.rates.as_ref().and_then(|r| r.get(&name));
+        let value = match rate {
+            Some(rate) => fetch_rate(rate, rpc)
+                .await
+                .map_err(|e| e.context(format!("rate {}", name)))?,
+            None => match coins.iter().position(|c| *c == name) {
+                Some(idx) if !prices[idx].is_zero() => prices[idx],
+                Some(_) => return Err(anyhow!("input {} has no price", name)),
+                None => return Err(anyhow!("unknown input {}", name)),
+            },
+        };
+        inputs.insert(name, value);
+    }
+
+    let price = formula::evaluate(&cfg.formula, &inputs)?;
+    if price <= Decimal::ZERO {
+        return Err(anyhow!("derived price {} not positive", price));
+    }
+    Ok(Evaluation { price, inputs })
+}
@@ -1,3 +1,4 @@
+use crate::decimal::{self, Decimal};
 use crate::request;
 use anyhow::{Ok, Result};
 use serde::Deserialize;
@@ -30,47 +31,50 @@ pub struct BinanceTickerResponseV2 {
     pub symbol: String,
     pub lastPrice: String,
     pub volume: String,
+    pub closeTime: u64,
 }
 
 pub async fn get_latest_price_v2(
     bases: &Vec<String>,
     currency: &str,
     _invalid_time: u64,
-) -> Result<(Vec<f64>, Vec<f64>)> {
+) -> Result<(Vec<Decimal>, Vec<Decimal>, Vec<u64>)> {
     let request_url: String = utils::get_latest_price_url_v2(bases, currency);
     let responses: Vec<BinanceTickerResponseV2> =
         request::request(&request_url).await?;
     let base_indexs = utils::get_pairs(bases, currency);
 
     let size = bases.len();
-    let mut vec_prices = vec![0.0f64; size];
-    let mut vec_volumes = vec![0.0f64; size];
+    let mut vec_prices = vec![Decimal::ZERO; size];
+    let mut vec_volumes = vec![Decimal::ZERO; size];
+    let mut vec_timestamps = vec![0u64; size];
 
     for t in responses.iter() {
         let index = *base_indexs.get(&(*t).symbol).unwrap();
-        let price: f64 = (*t).lastPrice.parse()?;
+        let price = decimal::parse(&(*t).lastPrice)?;
         vec_prices[index] = price;
-        let volume: f64 = (*t).volume.parse()?;
+        let volume = decimal::parse(&(*t).volume)?;
         vec_volumes[index] = volume;
+        vec_timestamps[index] = (*t).closeTime;
     }
-    Ok((vec_prices, vec_volumes))
+    Ok((vec_prices, vec_volumes, vec_timestamps))
 }
 
 pub async fn get_usdt_latest_price_v2(
     bases: &Vec<String>,
     currency: &str,
     _invalid_time: u64,
-) -> Result<(Vec<f64>, usize)> {
+) -> Result<(Vec<Decimal>, usize)> {
     let request_url: String = utils::get_latest_price_url("USDT", "USD");
     let response: BinanceTickerResponse =
         request::request(&request_url).await?;
     let base_indexs = utils::get_pairs(bases, currency);
 
     let size = bases.len();
-    let mut vec_prices = vec![0.0f64; size];
+    let mut vec_prices = vec![Decimal::ZERO; size];
 
     let index = *base_indexs.get(&response.symbol).unwrap();
-    let price: f64 = response.lastPrice.parse()?;
+    let price = decimal::parse(&response.lastPrice)?;
     vec_prices[index] = price;
 
     Ok((vec_prices, index))
@@ -1,3 +1,4 @@
+use crate::decimal::{self, Decimal};
 use crate::misc;
 use crate::request;
 use anyhow::{Ok, Result};
@@ -24,15 +25,16 @@ pub async fn get_latest_price_v2(
     bases: &Vec<String>,
     currency: &str,
     invalid_time: u64,
-) -> Result<(Vec<f64>, Vec<f64>)> {
+) -> Result<(Vec<Decimal>, Vec<Decimal>, Vec<u64>)> {
     let request_url: String = utils::get_latest_price_url_v2();
     let base_indexs = utils::get_pairs(bases, currency);
     let response: BitgetTickerResponseV2 =
         request::request(&request_url).await?;
 
     let size = bases.len();
-    let mut vec_prices = vec![0.0f64; size];
-    let mut vec_volumes = vec![0.0f64; size];
+    let mut vec_prices = vec![Decimal::ZERO; size];
+    let mut vec_volumes = vec![Decimal::ZERO; size];
+    let mut vec_timestamps = vec![0u64; size];
 
     for t in response.data.iter() {
         if base_indexs.contains_key(&(*t).symbol) {
@@ -42,11 +44,12 @@ pub async fn get_latest_price_v2(
                 continue;
             }
             let index = *base_indexs.get(&(*t).symbol).unwrap();
-            let price: f64 = (*t).close.parse()?;
+            let price = decimal::parse(&(*t).close)?;
             vec_prices[index] = price;
-            let volume: f64 = (*t).baseVol.parse()?;
+            let volume = decimal::parse(&(*t).baseVol)?;
             vec_volumes[index] = volume;
+            vec_timestamps[index] = ts;
         }
     }
-    Ok((vec_prices, vec_volumes))
+    Ok((vec_prices, vec_volumes, vec_timestamps))
 }
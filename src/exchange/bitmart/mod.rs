@@ -1,5 +1,6 @@
 use std::vec;
 
+use crate::decimal::{self, Decimal};
 use crate::request;
 use anyhow::{Ok, Result};
 use serde::Deserialize;
@@ -57,11 +58,11 @@ pub struct BitmartTicker {
     pub base_volume_24h: String,
 }
 
-pub async fn get_latest_price(base: &str, currency: &str) -> Result<f64> {
+pub async fn get_latest_price(base: &str, currency: &str) -> Result<Decimal> {
     let request_url: String = utils::get_latest_price_url(base, currency);
     let response: BitmartTickerResponse =
         request::request(&request_url).await?;
-    let price: f64 = response.data.last_price.parse()?;
+    let price = decimal::parse(&response.data.last_price)?;
     Ok(price)
 }
 
@@ -69,15 +70,16 @@ pub async fn get_latest_price_v2(
     bases: &Vec<String>,
     currency: &str,
     invalid_time: u64,
-) -> Result<(Vec<f64>, Vec<f64>)> {
+) -> Result<(Vec<Decimal>, Vec<Decimal>, Vec<u64>)> {
     let request_url: String = utils::get_latest_price_url_v2();
     let base_indexs = utils::get_pairs(bases, currency);
     let response: BitmartTickerResponseV2 =
         request::request(&request_url).await?;
 
     let size = bases.len();
-    let mut vec_prices = vec![0.0f64; size];
-    let mut vec_volumes = vec![0.0f64; size];
+    let mut vec_prices = vec![Decimal::ZERO; size];
+    let mut vec_volumes = vec![Decimal::ZERO; size];
+    let mut vec_timestamps = vec![0u64; size];
 
     for t in response.data.tickers.iter() {
         if base_indexs.contains_key(&(*t).symbol) {
@@ -87,12 +89,13 @@ pub async fn get_latest_price_v2(
                 continue;
             }
             let index = *base_indexs.get(&(*t).symbol).unwrap();
-            let price: f64 = (*t).last_price.parse()?;
+            let price = decimal::parse(&(*t).last_price)?;
             vec_prices[index] = price;
 
-            let volume: f64 = (*t).base_volume_24h.parse()?;
+            let volume = decimal::parse(&(*t).base_volume_24h)?;
             vec_volumes[index] = volume;
+            vec_timestamps[index] = ts;
         }
     }
-    Ok((vec_prices, vec_volumes))
+    Ok((vec_prices, vec_volumes, vec_timestamps))
 }
@@ -1,3 +1,5 @@
+use crate::decimal::{self, Decimal};
+use crate::misc;
 use crate::request;
 use anyhow::{Ok, Result};
 use serde::Deserialize;
@@ -22,18 +24,21 @@ pub async fn get_latest_price_v2(
     bases: &Vec<String>,
     currency: &str,
     _invalid_time: u64,
-) -> Result<Vec<f64>> {
+) -> Result<(Vec<Decimal>, Vec<u64>)> {
     let request_url: String = utils::get_latest_price_url("USDT", "USD");
     let response: CoinbaseTickerResponse =
         request::request(&request_url).await?;
     let base_indexs = utils::get_pairs(bases, currency);
 
     let size = bases.len();
-    let mut vec_prices = vec![0.0f64; size];
+    let mut vec_prices = vec![Decimal::ZERO; size];
+    let mut vec_timestamps = vec![0u64; size];
 
     let index = *base_indexs.get(&response.data.base).unwrap();
-    let price: f64 = response.data.amount.parse()?;
+    let price = decimal::parse(&response.data.amount)?;
     vec_prices[index] = price;
+    // Coinbase spot prices carry no quote time, so the fetch time stands in.
+    vec_timestamps[index] = misc::get_timestamp();
 
-    Ok(vec_prices)
+    Ok((vec_prices, vec_timestamps))
 }
@@ -1,3 +1,4 @@
+use crate::decimal::{self, Decimal};
 use crate::misc;
 use crate::request;
 use anyhow::{Ok, Result};
@@ -35,13 +36,13 @@ pub struct CryptoData {
     pub t: u64,
 }
 
-pub async fn get_latest_price(base: &str, currency: &str) -> Result<f64> {
+pub async fn get_latest_price(base: &str, currency: &str) -> Result<Decimal> {
     let request_url: String = utils::get_latest_price_url(base, currency);
     let response: CryptoTickerResponse = request::request(&request_url).await?;
     if response.result.data.len() == 0 {
         return Err(anyhow::anyhow!("Crypto {} missing attribute data", base));
     }
-    let price: f64 = response.result.data[0].a.parse()?;
+    let price = decimal::parse(&response.result.data[0].a)?;
     Ok(price)
 }
 
@@ -49,7 +50,7 @@ pub async fn get_latest_price_v2(
     bases: &Vec<String>,
     currency: &str,
     invalid_time: u64,
-) -> Result<Vec<f64>> {
+) -> Result<(Vec<Decimal>, Vec<u64>)> {
     let request_url: String = utils::get_latest_price_url("USDT", "USD");
     let response: CryptoTickerResponse = request::request(&request_url).await?;
     if response.result.data.len() == 0 {
@@ -58,17 +59,19 @@ pub async fn get_latest_price_v2(
     let base_indexs = utils::get_pairs(bases, currency);
 
     let size = bases.len();
-    let mut vec_prices = vec![0.0f64; size];
+    let mut vec_prices = vec![Decimal::ZERO; size];
+    let mut vec_timestamps = vec![0u64; size];
 
     let ts: u64 = response.result.data[0].t;
     let current_ts = misc::get_timestamp();
     if current_ts > ts + invalid_time {
-        return Ok(vec_prices);
+        return Ok((vec_prices, vec_timestamps));
     }
 
     let index = *base_indexs.get(&response.result.data[0].i).unwrap();
-    let price: f64 = response.result.data[0].a.parse()?;
+    let price = decimal::parse(&response.result.data[0].a)?;
     vec_prices[index] = price;
+    vec_timestamps[index] = ts;
 
-    Ok(vec_prices)
+    Ok((vec_prices, vec_timestamps))
 }
@@ -1,3 +1,5 @@
+use crate::decimal::{self, Decimal};
+use crate::misc;
 use crate::request;
 use anyhow::{Ok, Result};
 use serde::Deserialize;
@@ -16,23 +18,27 @@ pub async fn get_latest_price_v2(
     bases: &Vec<String>,
     currency: &str,
     _invalid_time: u64,
-) -> Result<(Vec<f64>, Vec<f64>)> {
+) -> Result<(Vec<Decimal>, Vec<Decimal>, Vec<u64>)> {
     let request_url: String = utils::get_latest_price_url_v2();
     let base_indexs = utils::get_pairs(bases, currency);
     let responses: Vec<GateTicker> = request::request(&request_url).await?;
+    // Gate tickers carry no quote time, so the fetch time stands in.
+    let fetched_ts = misc::get_timestamp();
 
     let size = bases.len();
-    let mut vec_prices = vec![0.0f64; size];
-    let mut vec_volumes = vec![0.0f64; size];
+    let mut vec_prices = vec![Decimal::ZERO; size];
+    let mut vec_volumes = vec![Decimal::ZERO; size];
+    let mut vec_timestamps = vec![0u64; size];
 
     for t in responses.iter() {
         if base_indexs.contains_key(&(*t).currency_pair) {
             let index = *base_indexs.get(&(*t).currency_pair).unwrap();
-            let price: f64 = (*t).last.parse()?;
+            let price = decimal::parse(&(*t).last)?;
             vec_prices[index] = price;
-            let volume: f64 = (*t).base_volume.parse()?;
+            let volume = decimal::parse(&(*t).base_volume)?;
             vec_volumes[index] = volume;
+            vec_timestamps[index] = fetched_ts;
         }
     }
-    Ok((vec_prices, vec_volumes))
+    Ok((vec_prices, vec_volumes, vec_timestamps))
 }
@@ -1,3 +1,4 @@
+use crate::decimal::{self, Decimal};
 use crate::misc;
 use crate::request;
 use anyhow::{Ok, Result};
@@ -43,10 +44,10 @@ pub struct HuobiTicker {
     pub amount: f64,
 }
 
-pub async fn get_latest_price(base: &str, currency: &str) -> Result<f64> {
+pub async fn get_latest_price(base: &str, currency: &str) -> Result<Decimal> {
     let request_url: String = utils::get_latest_price_url(base, currency);
     let response: HuobiTickerResponse = request::request(&request_url).await?;
-    let price: f64 = response.tick.close;
+    let price = decimal::from_f64(response.tick.close)?;
     Ok(price)
 }
 
@@ -54,29 +55,31 @@ pub async fn get_latest_price_v2(
     bases: &Vec<String>,
     currency: &str,
     invalid_time: u64,
-) -> Result<(Vec<f64>, Vec<f64>)> {
+) -> Result<(Vec<Decimal>, Vec<Decimal>, Vec<u64>)> {
     let request_url: String = utils::get_latest_price_url_v2();
     let base_indexs = utils::get_pairs(bases, currency);
     let response: HuobiTickerResponseV2 =
         request::request(&request_url).await?;
 
     let size = bases.len();
-    let mut vec_prices = vec![0.0f64; size];
-    let mut vec_volumes = vec![0.0f64; size];
+    let mut vec_prices = vec![Decimal::ZERO; size];
+    let mut vec_volumes = vec![Decimal::ZERO; size];
+    let mut vec_timestamps = vec![0u64; size];
 
     let ts: u64 = response.ts;
     let current_ts = misc::get_timestamp();
     if current_ts > ts + invalid_time {
-        return Ok((vec_prices, vec_volumes));
+        return Ok((vec_prices, vec_volumes, vec_timestamps));
     }
 
     for t in response.data.iter() {
         if base_indexs.contains_key(&(*t).symbol) {
             let index = *base_indexs.get(&(*t).symbol).unwrap();
-            let price: f64 = (*t).close;
+            let price = decimal::from_f64((*t).close)?;
             vec_prices[index] = price;
-            vec_volumes[index] = (*t).amount;
+            vec_volumes[index] = decimal::from_f64((*t).amount)?;
+            vec_timestamps[index] = ts;
         }
     }
-    Ok((vec_prices, vec_volumes))
+    Ok((vec_prices, vec_volumes, vec_timestamps))
 }
@@ -1,3 +1,4 @@
+use crate::decimal::{self, Decimal};
 use crate::misc;
 use crate::request;
 use anyhow::{Ok, Result};
@@ -22,7 +23,7 @@ pub async fn get_latest_price_v2(
     bases: &Vec<String>,
     currency: &str,
     _invalid_time: u64,
-) -> Result<Vec<f64>> {
+) -> Result<(Vec<Decimal>, Vec<u64>)> {
     let request_url: String = utils::get_latest_price_url("USDT", "ZUSD");
     let response: KrakenTickerResponse = request::request(&request_url).await?;
     if response.result.len() == 0 {
@@ -31,7 +32,8 @@ pub async fn get_latest_price_v2(
     let base_indexs = utils::get_pairs(bases, currency);
 
     let size = bases.len();
-    let mut vec_prices = vec![0.0f64; size];
+    let mut vec_prices = vec![Decimal::ZERO; size];
+    let mut vec_timestamps = vec![0u64; size];
 
     let pair = "USDTZUSD";
     let op = response.result.get(pair);
@@ -45,8 +47,10 @@ pub async fn get_latest_price_v2(
     }
 
     let index = *base_indexs.get(pair).unwrap();
-    let price: f64 = ticker.c[0].parse()?;
+    let price = decimal::parse(&ticker.c[0])?;
     vec_prices[index] = price;
+    // Kraken tickers carry no quote time, so the fetch time stands in.
+    vec_timestamps[index] = misc::get_timestamp();
 
-    Ok(vec_prices)
+    Ok((vec_prices, vec_timestamps))
 }
@@ -18,6 +18,28 @@ mod kraken;
 mod mexc;
 mod okx;
 
+/// Source names by storage slot, in the order `get_prices` fills them.
+pub const SOURCES: [&str; 10] = [
+    "binance", "okx", "huobi", "mexc", "bybit", "bitget", "gate", "coinbase",
+    "crypto", "kraken",
+];
+
+/// Quote currency of each source, used to normalize liquidity to usd.
+pub const QUOTES: [&str; 10] = [
+    "USDT", "USDT", "USDT", "USDT", "USDT", "USDT", "USDT", "USD", "USD", "USD",
+];
+
+pub fn source_index(name: &str) -> Option<usize> {
+    SOURCES.iter().position(|s| *s == name)
+}
+
+pub fn source_name(index: usize) -> &'static str {
+    match SOURCES.get(index) {
+        Some(name) => name,
+        None => "unknown",
+    }
+}
+
 pub async fn get_binance_price_v2(
     binance_shared_prices: Arc<Mutex<Storage>>,
     coins: &Vec<String>,
@@ -31,13 +53,11 @@ pub async fn get_binance_price_v2(
     let binance_task = tokio::spawn(async move {
         match binance::get_latest_price_v2(&bases, &cur, invalid_time).await {
             Ok(prices) => {
-                println!("binance: {:#?}", prices);
-                {
-                    let mut locked_prices = binance_shared_prices.lock().await;
-                    for i in 0..len {
-                        locked_prices.tickers[index].prices[i] = prices.0[i];
-                        locked_prices.tickers[index].volumes[i] = prices.1[i];
-                    }
+                let mut locked_prices = binance_shared_prices.lock().await;
+                for i in 0..len {
+                    locked_prices.tickers[index].prices[i] = prices.0[i];
+                    locked_prices.tickers[index].volumes[i] = prices.1[i];
+                    locked_prices.tickers[index].timestamps[i] = prices.2[i];
                 }
             }
             Err(e) => eprintln!("{:?}", e),
@@ -60,13 +80,9 @@ pub async fn get_binance_usdt_price_v2(
             .await
         {
             Ok((prices, usdt_index)) => {
-                println!("binance: {:#?}", prices);
-                {
-                    let mut locked_prices =
-                        binance_usdt_shared_prices.lock().await;
-                    locked_prices.tickers[index].prices[usdt_index] =
-                        prices[usdt_index];
-                }
+                let mut locked_prices = binance_usdt_shared_prices.lock().await;
+                locked_prices.tickers[index].prices[usdt_index] =
+                    prices[usdt_index];
             }
             Err(e) => eprintln!("{:?}", e),
         }
@@ -87,13 +103,11 @@ pub async fn get_huobi_price_v2(
     let huobi_task = tokio::spawn(async move {
         match huobi::get_latest_price_v2(&bases, &cur, invalid_time).await {
             Ok(prices) => {
-                println!("huobi: {:#?}", prices);
-                {
-                    let mut locked_prices = huobi_shared_prices.lock().await;
-                    for i in 0..len {
-                        locked_prices.tickers[index].prices[i] = prices.0[i];
-                        locked_prices.tickers[index].volumes[i] = prices.1[i];
-                    }
+                let mut locked_prices = huobi_shared_prices.lock().await;
+                for i in 0..len {
+                    locked_prices.tickers[index].prices[i] = prices.0[i];
+                    locked_prices.tickers[index].volumes[i] = prices.1[i];
+                    locked_prices.tickers[index].timestamps[i] = prices.2[i];
                 }
             }
             Err(e) => eprintln!("{:?}", e),
@@ -115,13 +129,11 @@ pub async fn get_okx_price_v2(
     let okx_task = tokio::spawn(async move {
         match okx::get_latest_price_v2(&bases, &cur, invalid_time).await {
             Ok(prices) => {
-                println!("okx: {:#?}", prices);
-                {
-                    let mut locked_prices = okx_shared_prices.lock().await;
-                    for i in 0..len {
-                        locked_prices.tickers[index].prices[i] = prices.0[i];
-                        locked_prices.tickers[index].volumes[i] = prices.1[i];
-                    }
+                let mut locked_prices = okx_shared_prices.lock().await;
+                for i in 0..len {
+                    locked_prices.tickers[index].prices[i] = prices.0[i];
+                    locked_prices.tickers[index].volumes[i] = prices.1[i];
+                    locked_prices.tickers[index].timestamps[i] = prices.2[i];
                 }
             }
             Err(e) => eprintln!("{:?}", e),
@@ -143,13 +155,11 @@ pub async fn get_mexc_price_v2(
     let mexc_task = tokio::spawn(async move {
         match mexc::get_latest_price_v2(&bases, &cur, invalid_time).await {
             Ok(prices) => {
-                println!("mexc: {:#?}", prices);
-                {
-                    let mut locked_prices = mexc_shared_prices.lock().await;
-                    for i in 0..len {
-                        locked_prices.tickers[index].prices[i] = prices.0[i];
-                        locked_prices.tickers[index].volumes[i] = prices.1[i];
-                    }
+                let mut locked_prices = mexc_shared_prices.lock().await;
+                for i in 0..len {
+                    locked_prices.tickers[index].prices[i] = prices.0[i];
+                    locked_prices.tickers[index].volumes[i] = prices.1[i];
+                    locked_prices.tickers[index].timestamps[i] = prices.2[i];
                 }
             }
             Err(e) => eprintln!("{:?}", e),
@@ -171,13 +181,11 @@ pub async fn get_bybit_price_v2(
     let bybit_task = tokio::spawn(async move {
         match bybit::get_latest_price_v2(&bases, &cur, invalid_time).await {
             Ok(prices) => {
-                println!("bybit: {:#?}", prices);
-                {
-                    let mut locked_prices = bybit_shared_prices.lock().await;
-                    for i in 0..len {
-                        locked_prices.tickers[index].prices[i] = prices.0[i];
-                        locked_prices.tickers[index].volumes[i] = prices.1[i];
-                    }
+                let mut locked_prices = bybit_shared_prices.lock().await;
+                for i in 0..len {
+                    locked_prices.tickers[index].prices[i] = prices.0[i];
+                    locked_prices.tickers[index].volumes[i] = prices.1[i];
+                    locked_prices.tickers[index].timestamps[i] = prices.2[i];
                 }
             }
             Err(e) => eprintln!("{:?}", e),
@@ -199,13 +207,11 @@ pub async fn get_bitmart_price_v2(
     let bitmart_task = tokio::spawn(async move {
         match bitmart::get_latest_price_v2(&bases, &cur, invalid_time).await {
             Ok(prices) => {
-                println!("bitmart: {:#?}", prices);
-                {
-                    let mut locked_prices = bitmart_shared_prices.lock().await;
-                    for i in 0..len {
-                        locked_prices.tickers[index].prices[i] = prices.0[i];
-                        locked_prices.tickers[index].volumes[i] = prices.1[i];
-                    }
+                let mut locked_prices = bitmart_shared_prices.lock().await;
+                for i in 0..len {
+                    locked_prices.tickers[index].prices[i] = prices.0[i];
+                    locked_prices.tickers[index].volumes[i] = prices.1[i];
+                    locked_prices.tickers[index].timestamps[i] = prices.2[i];
                 }
             }
             Err(e) => eprintln!("{:?}", e),
@@ -227,13 +233,11 @@ pub async fn get_bitget_price_v2(
     let bitget_task = tokio::spawn(async move {
         match bitget::get_latest_price_v2(&bases, &cur, invalid_time).await {
             Ok(prices) => {
-                println!("bitget: {:#?}", prices);
-                {
-                    let mut locked_prices = bitget_shared_prices.lock().await;
-                    for i in 0..len {
-                        locked_prices.tickers[index].prices[i] = prices.0[i];
-                        locked_prices.tickers[index].volumes[i] = prices.1[i];
-                    }
+                let mut locked_prices = bitget_shared_prices.lock().await;
+                for i in 0..len {
+                    locked_prices.tickers[index].prices[i] = prices.0[i];
+                    locked_prices.tickers[index].volumes[i] = prices.1[i];
+                    locked_prices.tickers[index].timestamps[i] = prices.2[i];
                 }
             }
             Err(e) => eprintln!("{:?}", e),
@@ -255,13 +259,11 @@ pub async fn get_gate_price_v2(
     let gate_task = tokio::spawn(async move {
         match gate::get_latest_price_v2(&bases, &cur, invalid_time).await {
             Ok(prices) => {
-                println!("gate: {:#?}", prices);
-                {
-                    let mut locked_prices = gate_shared_prices.lock().await;
-                    for i in 0..len {
-                        locked_prices.tickers[index].prices[i] = prices.0[i];
-                        locked_prices.tickers[index].volumes[i] = prices.1[i];
-                    }
+                let mut locked_prices = gate_shared_prices.lock().await;
+                for i in 0..len {
+                    locked_prices.tickers[index].prices[i] = prices.0[i];
+                    locked_prices.tickers[index].volumes[i] = prices.1[i];
+                    locked_prices.tickers[index].timestamps[i] = prices.2[i];
                 }
             }
             Err(e) => eprintln!("{:?}", e),
@@ -283,12 +285,10 @@ pub async fn get_coinbase_price_v2(
     let coinbase_task = tokio::spawn(async move {
         match coinbase::get_latest_price_v2(&bases, &cur, invalid_time).await {
             Ok(prices) => {
-                println!("coinbase: {:#?}", prices);
-                {
-                    let mut locked_prices = coinbase_shared_prices.lock().await;
-                    for i in 0..len {
-                        locked_prices.tickers[index].prices[i] = prices[i];
-                    }
+                let mut locked_prices = coinbase_shared_prices.lock().await;
+                for i in 0..len {
+                    locked_prices.tickers[index].prices[i] = prices.0[i];
+                    locked_prices.tickers[index].timestamps[i] = prices.1[i];
                 }
             }
             Err(e) => eprintln!("{:?}", e),
@@ -310,12 +310,10 @@ pub async fn get_crypto_price_v2(
     let crypto_task = tokio::spawn(async move {
         match crypto::get_latest_price_v2(&bases, &cur, invalid_time).await {
             Ok(prices) => {
-                println!("crypto: {:#?}", prices);
-                {
-                    let mut locked_prices = crypto_shared_prices.lock().await;
-                    for i in 0..len {
-                        locked_prices.tickers[index].prices[i] = prices[i];
-                    }
+                let mut locked_prices = crypto_shared_prices.lock().await;
+                for i in 0..len {
+                    locked_prices.tickers[index].prices[i] = prices.0[i];
+                    locked_prices.tickers[index].timestamps[i] = prices.1[i];
                 }
             }
             Err(e) => eprintln!("{:?}", e),
@@ -337,12 +335,10 @@ pub async fn get_kraken_price_v2(
     let kraken_task = tokio::spawn(async move {
         match kraken::get_latest_price_v2(&bases, &cur, invalid_time).await {
             Ok(prices) => {
-                println!("kraken: {:#?}", prices);
-                {
-                    let mut locked_prices = kraken_shared_prices.lock().await;
-                    for i in 0..len {
-                        locked_prices.tickers[index].prices[i] = prices[i];
-                    }
+                let mut locked_prices = kraken_shared_prices.lock().await;
+                for i in 0..len {
+                    locked_prices.tickers[index].prices[i] = prices.0[i];
+                    locked_prices.tickers[index].timestamps[i] = prices.1[i];
                 }
             }
             Err(e) => eprintln!("{:?}", e),
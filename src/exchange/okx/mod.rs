@@ -1,3 +1,4 @@
+use crate::decimal::{self, Decimal};
 use crate::misc;
 use crate::request;
 use anyhow::{Ok, Result};
@@ -53,14 +54,15 @@ pub async fn get_latest_price_v2(
     bases: &Vec<String>,
     currency: &str,
     invalid_time: u64,
-) -> Result<(Vec<f64>, Vec<f64>)> {
+) -> Result<(Vec<Decimal>, Vec<Decimal>, Vec<u64>)> {
     let request_url: String = utils::get_latest_price_url_v2();
     let base_indexs = utils::get_pairs(bases, currency);
     let response: OKXTickerResponseV2 = request::request(&request_url).await?;
 
     let size = bases.len();
-    let mut vec_prices = vec![0.0f64; size];
-    let mut vec_volumes = vec![0.0f64; size];
+    let mut vec_prices = vec![Decimal::ZERO; size];
+    let mut vec_volumes = vec![Decimal::ZERO; size];
+    let mut vec_timestamps = vec![0u64; size];
 
     for t in response.data.iter() {
         if base_indexs.contains_key(&(*t).instId) {
@@ -71,11 +73,12 @@ pub async fn get_latest_price_v2(
             }
 
             let index = *base_indexs.get(&(*t).instId).unwrap();
-            let price: f64 = (*t).last.parse()?;
+            let price = decimal::parse(&(*t).last)?;
             vec_prices[index] = price;
-            let volume: f64 = (*t).vol24h.parse()?;
+            let volume = decimal::parse(&(*t).vol24h)?;
             vec_volumes[index] = volume;
+            vec_timestamps[index] = ts;
         }
     }
-    Ok((vec_prices, vec_volumes))
+    Ok((vec_prices, vec_volumes, vec_timestamps))
 }
@@ -0,0 +1,179 @@
+use anyhow::{anyhow, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+pub const VERSION: u32 = 1;
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+
+/// A key or mnemonic sealed with xchacha20-poly1305 under an argon2id key
+/// derived from a passphrase.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedKey {
+    pub version: u32,
+    /// Argon2id memory in KiB, iterations and lanes.
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+fn derive_key(
+    passphrase: &[u8],
+    salt: &[u8],
+    params: Params,
+) -> Result<Zeroizing<[u8; KEY_LEN]>> {
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, salt, key.as_mut())
+        .map_err(|e| anyhow!("kdf: {}", e))?;
+    Ok(key)
+}
+
+impl EncryptedKey {
+    pub fn encrypt(secret: &[u8], passphrase: &[u8]) -> Result<EncryptedKey> {
+        let params = Params::default();
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let key = derive_key(passphrase, &salt, params.clone())?;
+
+        let cipher = XChaCha20Poly1305::new(key.as_ref().into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, secret)
+            .map_err(|_| anyhow!("encryption failed"))?;
+
+        Ok(EncryptedKey {
+            version: VERSION,
+            m_cost: params.m_cost(),
+            t_cost: params.t_cost(),
+            p_cost: params.p_cost(),
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    /// Fails on a wrong passphrase or a tampered file alike.
+    pub fn decrypt(&self, passphrase: &[u8]) -> Result<Zeroizing<String>> {
+        if self.version != VERSION {
+            return Err(anyhow!(
+                "unsupported keystore version {}",
+                self.version
+            ));
+        }
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, None)
+            .map_err(|e| anyhow!("kdf: {}", e))?;
+        let key = derive_key(passphrase, &hex::decode(&self.salt)?, params)?;
+
+        let nonce = hex::decode(&self.nonce)?;
+        if nonce.len() != 24 {
+            return Err(anyhow!("invalid nonce"));
+        }
+        let cipher = XChaCha20Poly1305::new(key.as_ref().into());
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(
+                    XNonce::from_slice(&nonce),
+                    &hex::decode(&self.ciphertext)?[..],
+                )
+                .map_err(|_| {
+                    anyhow!("wrong passphrase or corrupted keystore")
+                })?,
+        );
+        let secret = std::str::from_utf8(&plaintext)?;
+        Ok(Zeroizing::new(secret.to_owned()))
+    }
+
+    pub fn read(path: &Path) -> Result<EncryptedKey> {
+        let f = std::fs::File::open(path)
+            .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        Ok(serde_json::from_reader(f)?)
+    }
+
+    /// Creates the file readable by the owner only. An existing file is
+    /// never overwritten.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let f = options
+            .open(path)
+            .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        serde_json::to_writer_pretty(f, self)?;
+        Ok(())
+    }
+}
+
+/// Reads the passphrase from the first line of `file`, or else prompts for
+/// it without echo.
+pub fn read_passphrase(
+    file: Option<&str>,
+    prompt: &str,
+) -> Result<Zeroizing<String>> {
+    match file {
+        Some(file) => {
+            let content = Zeroizing::new(std::fs::read_to_string(file)?);
+            let line = content.lines().next().unwrap_or_default();
+            Ok(Zeroizing::new(line.to_owned()))
+        }
+        None => Ok(Zeroizing::new(rpassword::prompt_password(prompt)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MNEMONIC: &str = "film crazy soon outside stand loop subway crumble \
+        thrive popular green nuclear struggle pistol arm wife phrase warfare \
+        march wheat nephew ask sunny firm";
+
+    #[test]
+    fn round_trip() {
+        let encrypted =
+            EncryptedKey::encrypt(MNEMONIC.as_bytes(), b"pass").unwrap();
+        let secret = encrypted.decrypt(b"pass").unwrap();
+        assert_eq!(secret.as_str(), MNEMONIC);
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let encrypted =
+            EncryptedKey::encrypt(MNEMONIC.as_bytes(), b"pass").unwrap();
+        let e = encrypted.decrypt(b"Pass").unwrap_err();
+        assert!(e.to_string().contains("wrong passphrase"));
+    }
+
+    #[test]
+    fn write_keeps_an_existing_file() {
+        let path = std::env::temp_dir()
+            .join(format!("tiny-keystore-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let first = EncryptedKey::encrypt(b"first", b"pass").unwrap();
+        first.write(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let second = EncryptedKey::encrypt(b"second", b"pass").unwrap();
+        assert!(second.write(&path).is_err());
+        let read = EncryptedKey::read(&path).unwrap();
+        assert_eq!(read.decrypt(b"pass").unwrap().as_str(), "first");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
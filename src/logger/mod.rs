@@ -32,9 +32,12 @@ pub struct Logger {
 }
 
 impl Logger {
-    pub fn new(open: bool, use_cfg: bool) -> Logger {
-        let roll_file = configuration::get_log_path(ROLL_PATTERN).unwrap();
-        let log_file = configuration::get_log_path(LOG_FILE_NAME).unwrap();
+    /// Log files go to `dir`, or next to the executable without one.
+    pub fn new(open: bool, use_cfg: bool, dir: Option<&str>) -> Logger {
+        let roll_file =
+            configuration::get_data_path(dir, ROLL_PATTERN).unwrap();
+        let log_file =
+            configuration::get_data_path(dir, LOG_FILE_NAME).unwrap();
         Logger {
             roll_name: roll_file,
             file_name: log_file,
@@ -59,36 +62,39 @@ impl Logger {
 
         let encoder = PatternEncoder::new(DEFAULT_DAEMON_FILE_FORMAT);
 
-        let appender = RollingFileAppender::builder()
-            .encoder(Box::new(encoder))
-            .append(true)
-            .build(&self.file_name, Box::new(compound_policy))?;
-
-        let file = Appender::builder().build(LOG, Box::new(appender));
         let stdout = Appender::builder().build(STDOUT, Box::new(console));
+        let mut config = Config::builder().appender(stdout);
+        let mut root = Root::builder().appender(STDOUT);
 
-        let root = Root::builder()
-            .appender(STDOUT)
-            .appender(LOG)
-            .build(LevelFilter::Info);
+        // A read only file system still gets console logs.
+        match RollingFileAppender::builder()
+            .encoder(Box::new(encoder))
+            .append(true)
+            .build(&self.file_name, Box::new(compound_policy))
+        {
+            Ok(appender) => {
+                config = config.appender(
+                    Appender::builder().build(LOG, Box::new(appender)),
+                );
+                root = root.appender(LOG);
+            }
+            Err(e) => eprintln!("log file {}: {}", self.file_name, e),
+        }
 
-        let config = Config::builder()
-            .appender(stdout)
-            .appender(file)
-            .build(root)?;
+        let config = config.build(root.build(LevelFilter::Info))?;
 
         Ok(log4rs::init_config(config)?)
     }
 }
 
-pub fn init_logger(open: bool, use_cfg: bool) {
+pub fn init_logger(open: bool, use_cfg: bool, dir: Option<&str>) {
     if use_cfg {
         let log_path = configuration::get_log_path("log4rs.yaml");
         log4rs::init_file(&log_path.unwrap(), Default::default()).unwrap();
         return;
     }
 
-    let logger = Logger::new(open, use_cfg);
+    let logger = Logger::new(open, use_cfg, dir);
     match logger.init() {
         Ok(_) => println!("logger init succeeded"),
         Err(e) => eprint!("logger init failed: {}", e),
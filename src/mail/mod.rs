@@ -2,6 +2,10 @@ use mail_send::mail_builder::MessageBuilder;
 use mail_send::{Error, SmtpClientBuilder};
 use std::sync::atomic::{AtomicU64, Ordering};
 
+use crate::balance::Level;
+use crate::decimal::Decimal;
+use crate::sink::VerifyError;
+
 static GID: AtomicU64 = AtomicU64::new(1);
 pub const BASE_SUI_UNIT: u64 = 1000000000;
 
@@ -9,6 +13,7 @@ pub const BASE_SUI_UNIT: u64 = 1000000000;
 pub enum AlarmType {
     Balance,
     Price,
+    Depeg,
 }
 
 #[derive(Debug, Clone)]
@@ -69,15 +74,61 @@ impl Alarm {
     }
 }
 
-pub fn new_balance_alarm(balance: u64, threshold: u64) -> Alarm {
-    let balance = balance as f64 / BASE_SUI_UNIT as f64;
-    let threshold = threshold as f64 / BASE_SUI_UNIT as f64;
+/// Sent when an account's balance level changes. `Level::Ok` means it
+/// recovered.
+pub fn new_balance_alarm(
+    account: &str,
+    level: Level,
+    balance: f64,
+    threshold: Option<f64>,
+    runway_days: Option<f64>,
+) -> Alarm {
+    let runway = match runway_days {
+        Some(days) => format!("{:.1} days", days),
+        None => "unknown".to_string(),
+    };
+    let desc = match (level, threshold) {
+        (Level::Ok, _) | (_, None) => format!(
+            "{} balance {} recovered, runway {}",
+            account, balance, runway
+        ),
+        (_, Some(threshold)) => format!(
+            "{} balance {} below {}, runway {}",
+            account, balance, threshold, runway
+        ),
+    };
+    let subject = match level {
+        Level::Critical => "Critical Balance Alarm",
+        _ => "Balance Alarm",
+    };
+    Alarm::new(0, AlarmType::Balance, subject.to_string(), desc)
+}
 
+pub fn new_top_up_alarm(
+    account: &str,
+    amount: f64,
+    digest: Option<&str>,
+) -> Alarm {
     Alarm::new(
         0,
         AlarmType::Balance,
-        "Balance Alarm".to_string(),
-        format!("Balance: {}, below {}", balance, threshold),
+        "Balance Top Up".to_string(),
+        format!(
+            "{} topped up with {} from the treasury, tx {}",
+            account,
+            amount,
+            digest.unwrap_or("unknown")
+        ),
+    )
+}
+
+/// Sent when a sink's update landed but does not read back as sent.
+pub fn new_verify_alarm(sink: &str, error: &VerifyError) -> Alarm {
+    Alarm::new(
+        0,
+        AlarmType::Price,
+        "Price Verify Alarm".to_string(),
+        format!("{}: {}", sink, error),
     )
 }
 
@@ -89,3 +140,40 @@ pub fn new_price_alarm(desc: &str) -> Alarm {
         desc.to_owned(),
     )
 }
+
+pub fn new_depeg_alarm(
+    symbol: &str,
+    market: Decimal,
+    reference: Decimal,
+    diff_bps: Decimal,
+    depegged: bool,
+) -> Alarm {
+    let desc = if depegged {
+        format!(
+            "{} depegged: market {}, peg {}, diff {} bps. Publishing market price.",
+            symbol,
+            market,
+            reference,
+            diff_bps.round_dp(2)
+        )
+    } else {
+        format!(
+            "{} back on peg: market {}, peg {}, diff {} bps.",
+            symbol,
+            market,
+            reference,
+            diff_bps.round_dp(2)
+        )
+    };
+
+    Alarm::new(0, AlarmType::Depeg, "Depeg Alarm".to_string(), desc)
+}
+
+pub fn new_bounds_alarm(symbol: &str, price: Decimal, reason: &str) -> Alarm {
+    Alarm::new(
+        0,
+        AlarmType::Price,
+        "Price Bounds Alarm".to_string(),
+        format!("{} price {} dropped: {}", symbol, price, reason),
+    )
+}
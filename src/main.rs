@@ -1,44 +1,79 @@
 #![allow(dead_code, unused_imports)]
-use anyhow::Result;
-use clap::Parser;
-use configuration::{Configuration, SuiKey};
+use algorithm::{Primaries, Weighting};
+use anyhow::{anyhow, Result};
+use bip32::DerivationPath;
+use clap::{Parser, Subcommand};
+use configuration::{
+    Configuration, CosignConfig, EvmSinkConfig, KeystoreConfig, SinkConfig,
+    StarknetSinkConfig, SuiKey,
+};
+use decimal::Decimal;
 use dotenv::dotenv;
 use lazy_static::lazy_static;
 use log::{error, info, warn};
 use log4rs::filter::threshold;
 use logger::Logger;
 use mail::{Alarm, AlarmType};
+use mov::rpc::RpcPool;
+use mov::treasury::Treasury;
+use peg::Peg;
 use postage::{broadcast, broadcast::Sender, prelude::Stream, sink::Sink};
 use prom::Prom;
+use recorder::{Entry, Recorder, RoundRecord, TxRecord};
+use signer::{LocalSigner, RemoteSigner, Signer};
 use single_instance::SingleInstance;
-use std::io;
+use sink::{evm::EvmSink, starknet::StarknetSink, sui::SuiSink};
+use sink::{Batch, PriceSink, VerifyError};
+use smoothing::Smoother;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::IsTerminal;
+use std::path::Path;
 use std::process;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use storage::Storage;
-use sui_json::SuiJsonValue;
-use sui_sdk::wallet_context::WalletContext;
+use sui_sdk::types::base_types::SuiAddress;
+use sui_types::base_types::ObjectID;
+use sui_types::crypto::{EncodeDecodeBase64, SignatureScheme, SuiKeyPair};
 use tokio::signal;
 use tokio::sync::Mutex;
-use tokio::sync::MutexGuard;
 use tokio::time;
+use zeroize::Zeroizing;
 
 mod algorithm;
+mod balance;
+mod bounds;
 mod configuration;
+mod cosign;
 mod daemon;
+mod decimal;
+mod derived;
 mod exchange;
+mod keystore;
 mod logger;
 mod mail;
 mod misc;
 mod mov;
+mod peg;
 mod prom;
+mod recorder;
+mod replay;
 mod request;
+mod signer;
+mod sink;
+mod smoothing;
 mod storage;
 
 pub const EXCHANGE_SIZE: usize = 20;
-pub const ORACLE_MODULE: &str = "oracle";
-pub const ORACLE_FEED_FUNCTION: &str = "update_token_price_batch";
 pub const SINGLE_INSTANCE_ID: &str = "Tiny Oracle Feeder";
+pub const DEFAULT_PRIMARIES: [&str; 2] = ["binance", "okx"];
+pub const DEFAULT_MIN_SOURCES: usize = 4;
+pub const DEFAULT_PEG_BAND_BPS: u32 = 100;
+pub const HISTORY_FILE: &str = "history.json";
+pub const DEFAULT_FRESHNESS_HALF_LIFE: u64 = 60;
+pub const SIGNER_KEY_ENV: &str = "SIGNER_KEY";
+pub const DEFAULT_ROTATE_ROUNDS: u64 = 1000;
+pub const TOP_UP_LOG_FILE: &str = "top_ups.json";
 
 lazy_static! {
     static ref CFG: Configuration =
@@ -55,10 +90,16 @@ lazy_static! {
         let sk = SuiKey {
             key: "".to_owned(),
             mnemonic: "".to_owned(),
+            scheme: "".to_owned(),
+            derivation_path: "".to_owned(),
         };
         std::sync::Mutex::new(sk)
     };
-    static ref RPCINDEX: std::sync::Mutex<u64> = std::sync::Mutex::new(0);
+    static ref PRIMARIES: Primaries = get_primaries().unwrap();
+    static ref RECORDER: std::sync::Mutex<Option<Recorder>> =
+        std::sync::Mutex::new(get_recorder());
+    static ref RPC_POOLS: Mutex<HashMap<Vec<String>, RpcPool>> =
+        Mutex::new(HashMap::new());
 }
 
 #[derive(Parser, Debug)]
@@ -79,65 +120,272 @@ struct Cli {
     #[clap(short, long, default_value = "")]
     /// Mnemonic. BIP 39.
     mnemonic: String,
+
+    #[clap(long, default_value = "")]
+    /// Key scheme: ed25519, secp256k1 or secp256r1.
+    scheme: String,
+
+    #[clap(long, default_value = "")]
+    /// BIP 32 derivation path of the mnemonic.
+    derivation_path: String,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
 }
 
-fn is_coin_analog(symbol: &str) -> (bool, f64) {
-    if CFG.imitations.is_some() {
-        let imitations = CFG.imitations.clone().unwrap();
-        let result = imitations.get(symbol);
-        if result.is_some() {
-            return (true, *result.unwrap());
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Replays recorded source snapshots through the aggregation and prints
+    /// a per-coin report. Nothing is sent on-chain.
+    Replay {
+        /// Snapshot jsonl file, or a directory of them.
+        path: String,
+
+        #[clap(short, long)]
+        /// Config to replay against instead of tiny.yaml.
+        config: Option<String>,
+    },
+    /// Manages the encrypted keystore.
+    Key {
+        #[clap(subcommand)]
+        action: KeyAction,
+    },
+    /// Signs whatever it is sent with the key in SIGNER_KEY. A local stand-in
+    /// for an external signer.
+    SignerStub {
+        /// Loopback address for http, or `unix:/path`.
+        listen: String,
+    },
+    /// Serves as a multisig co-signer. Proposed updates are signed only when
+    /// their prices are within tolerance of prices fetched here.
+    Cosign {
+        #[clap(short, long)]
+        /// Config to use instead of tiny.yaml.
+        config: Option<String>,
+    },
+}
+
+/// Fails on unknown source names so a typo cannot leave the consensus
+/// without a primary.
+fn get_primaries() -> Result<Primaries> {
+    let (names, min_sources, fallback) = match &CFG.consensus {
+        Some(c) => (c.primaries.clone(), c.min_sources, c.fallback),
+        None => (
+            DEFAULT_PRIMARIES.iter().map(|s| s.to_string()).collect(),
+            DEFAULT_MIN_SOURCES,
+            true,
+        ),
+    };
+
+    let mut sources = Vec::new();
+    for name in names.iter() {
+        match exchange::source_index(name) {
+            Some(idx) => sources.push(idx),
+            None => return Err(anyhow!("unknown primary source {}", name)),
         }
     }
+    if sources.is_empty() {
+        return Err(anyhow!("no primary sources"));
+    }
+
+    Ok(Primaries {
+        sources,
+        min_sources,
+        fallback,
+    })
+}
 
-    (false, 0.0f64)
+/// Where written files go, see `data_dir`.
+fn data_path(file: &str) -> Option<String> {
+    configuration::get_data_path(CFG.data_dir.as_deref(), file)
+}
+
+fn get_recorder() -> Option<Recorder> {
+    let cfg = CFG.recorder.as_ref()?;
+    let dir = data_path(&cfg.dir)?;
+    let rotate_rounds = cfg.rotate_rounds.unwrap_or(DEFAULT_ROTATE_ROUNDS);
+    match Recorder::new(&dir, rotate_rounds, cfg.keep_files) {
+        Ok(recorder) => Some(recorder),
+        Err(e) => {
+            error!("recorder {} unavailable: {}", dir, e);
+            None
+        }
+    }
+}
+
+fn record(entry: Entry) {
+    let mut recorder = RECORDER.lock().unwrap();
+    if let Some(recorder) = recorder.as_mut() {
+        if let Err(e) = recorder.record(&entry) {
+            error!("record failed: {}", e);
+        }
+    }
+}
+
+fn get_peg(symbol: &str) -> Option<Peg> {
+    if let Some(pegs) = &CFG.pegs {
+        if let Some(p) = pegs.get(symbol) {
+            let reference = match (&p.value, &p.target) {
+                (Some(value), _) => peg::Reference::Value(*value),
+                (None, Some(target)) => {
+                    match CFG.coins.iter().position(|c| c == target) {
+                        Some(idx) => peg::Reference::Coin(idx),
+                        None => {
+                            error!(
+                                "{} peg target {} not in coins",
+                                symbol, target
+                            );
+                            return None;
+                        }
+                    }
+                }
+                (None, None) => {
+                    error!("{} peg needs a value or a target", symbol);
+                    return None;
+                }
+            };
+            return Some(Peg {
+                reference,
+                band_bps: peg::to_bps(p.band),
+            });
+        }
+    }
+
+    if let Some(imitations) = &CFG.imitations {
+        if let Some(value) = imitations.get(symbol) {
+            return Some(Peg {
+                reference: peg::Reference::Value(*value),
+                band_bps: DEFAULT_PEG_BAND_BPS,
+            });
+        }
+    }
+
+    None
+}
+
+async fn apply_peg(
+    symbol: &str,
+    peg: &Peg,
+    market: Decimal,
+    prices: &Vec<Decimal>,
+    tx: &mut Sender<Alarm>,
+) -> Decimal {
+    let reference = match peg.reference {
+        peg::Reference::Value(value) => value,
+        peg::Reference::Coin(idx) => prices[idx],
+    };
+
+    if reference.is_zero() {
+        error!("{} peg reference missing, using market price", symbol);
+        return market;
+    }
+    if market.is_zero() {
+        warn!(
+            "{} market price missing, publishing peg {}",
+            symbol, reference
+        );
+        return reference;
+    }
+
+    let outcome = peg::resolve(symbol, market, reference, peg.band_bps);
+    if outcome.depegged {
+        warn!(
+            "{} depegged: market {}, peg {}, diff {} bps",
+            symbol, market, reference, outcome.diff_bps
+        );
+    }
+    if outcome.changed && CFG.enable_price_alarm {
+        let alarm = mail::new_depeg_alarm(
+            symbol,
+            market,
+            reference,
+            outcome.diff_bps,
+            outcome.depegged,
+        );
+        _ = tx.send(alarm).await;
+    }
+    outcome.price
+}
+
+fn get_weighting(
+    timestamps: &Vec<u64>,
+    usdt_price: Decimal,
+    now: u64,
+) -> Weighting {
+    let (half_life, max_weight) = match &CFG.liquidity {
+        Some(l) => (l.half_life, l.max_weight),
+        None => (DEFAULT_FRESHNESS_HALF_LIFE, Decimal::ONE),
+    };
+    let trusts = CFG.liquidity.as_ref().and_then(|l| l.trust.as_ref());
+
+    let mut usd_rates = vec![Decimal::ONE; EXCHANGE_SIZE];
+    let mut trust = vec![Decimal::ONE; EXCHANGE_SIZE];
+    for i in 0..EXCHANGE_SIZE {
+        if exchange::QUOTES.get(i) == Some(&"USDT") {
+            usd_rates[i] = usdt_price;
+        }
+        if let Some(t) = trusts.and_then(|t| t.get(exchange::source_name(i))) {
+            trust[i] = *t;
+        }
+    }
+
+    Weighting {
+        ages: timestamps
+            .iter()
+            .map(|ts| now.saturating_sub(*ts))
+            .collect(),
+        usd_rates,
+        trust,
+        half_life,
+        max_weight,
+    }
 }
 
 fn get_coin_price(
     symbol: &str,
     idx: usize,
-    locked_prices: &MutexGuard<'_, Storage>,
-) -> (bool, f64) {
-    let coin_price;
+    prices: &Storage,
+    usdt_price: Decimal,
+    now: u64,
+) -> (bool, Decimal) {
     let count = CFG.coins.len();
-    let (coin_analog, coin_pri) = is_coin_analog(symbol);
-    if coin_analog {
-        coin_price = coin_pri;
+    let data = get_price_info_v2(prices, idx, count);
+
+    let len = CFG.algorithms.len();
+    let idx;
+    if symbol != "USDT" {
+        idx = (CFG.active as usize % len) as usize;
     } else {
-        let data = get_price_info_v2(locked_prices, idx, count);
+        idx = (CFG.usdt_active as usize % len) as usize;
+    }
+    let algo = &CFG.algorithms[idx];
 
-        let len = CFG.algorithms.len();
-        let idx;
-        if symbol != "USDT" {
-            idx = (CFG.active as usize % len) as usize;
-        } else {
-            idx = (CFG.usdt_active as usize % len) as usize;
-        }
-        let algo = &CFG.algorithms[idx];
+    let weighting = get_weighting(&data.2, usdt_price, now);
 
-        let mut diff = 0.001f64;
-        if CFG.diffs.contains_key(symbol) {
-            diff = *CFG.diffs.get(symbol).unwrap();
-        }
+    let mut diff = 0.001f64;
+    if CFG.diffs.contains_key(symbol) {
+        diff = *CFG.diffs.get(symbol).unwrap();
+    }
 
-        let (success, tmp_price) = algorithm::switch_algo(
-            algo,
-            data.0,
-            data.1,
-            Option::Some(diff),
-            Option::Some(CFG.ratio),
-        );
+    let (success, coin_price) = algorithm::switch_algo(
+        symbol,
+        algo,
+        data.0,
+        data.1,
+        Option::Some(diff),
+        Option::Some(CFG.ratio),
+        &PRIMARIES,
+        &weighting,
+    );
 
-        if !success {
-            error!("get {} price failed", symbol);
-            return (false, 0.0);
-        }
-        coin_price = tmp_price;
+    if !success {
+        error!("get {} price failed", symbol);
+        return (false, Decimal::ZERO);
     }
     (true, coin_price)
 }
 
-async fn get_prices(coins: &Vec<String>) -> Result<Vec<f64>> {
+async fn fetch_prices(coins: &Vec<String>) -> Storage {
     let prices = Storage::new(EXCHANGE_SIZE, EXCHANGE_SIZE);
     let shared_prices = Arc::new(Mutex::new(prices));
 
@@ -254,70 +502,281 @@ async fn get_prices(coins: &Vec<String>) -> Result<Vec<f64>> {
         kraken_task,
     );
 
-    let mut_locked = shared_prices.clone();
-    let locked_prices = mut_locked.lock().await;
+    let storage = shared_prices.lock().await;
+    storage.clone()
+}
 
+async fn aggregate_prices(
+    coins: &Vec<String>,
+    prices: &Storage,
+    now: u64,
+    tx: &mut Sender<Alarm>,
+) -> Result<Vec<Decimal>> {
     let usdt_idx = coins.iter().position(|x| x == "USDT").unwrap();
     info!("usdt idx = {}", usdt_idx);
 
-    let (succeed, usdt_price) =
-        get_coin_price("USDT", usdt_idx, &locked_prices);
-    if !succeed {
+    let size = coins.len();
+    let mut result = vec![Decimal::ZERO; size];
+
+    let (_, usdt_market) =
+        get_coin_price("USDT", usdt_idx, prices, Decimal::ONE, now);
+    let usdt_price = match get_peg("USDT") {
+        Some(peg) => apply_peg("USDT", &peg, usdt_market, &result, tx).await,
+        None => usdt_market,
+    };
+    if usdt_price.is_zero() {
         error!("fetch usdt price failed");
         return Err(anyhow::anyhow!("fetch usdt price failed"));
     }
-
-    let size = coins.len();
-    let mut result = vec![0.0f64; size];
     result[usdt_idx] = usdt_price;
 
+    let mut markets = vec![Decimal::ZERO; size];
     for idx in 0..size {
-        if idx == usdt_idx {
+        // Derived coins have no quotes of their own.
+        if idx == usdt_idx || is_derived(&coins[idx]) {
             continue;
         }
 
         let (succeed, coin_price) =
-            get_coin_price(&coins[idx], idx, &locked_prices);
+            get_coin_price(&coins[idx], idx, prices, usdt_price, now);
         if !succeed {
             error!("get {} price failed", coins[idx]);
             continue;
         }
 
-        result[idx] = coin_price * usdt_price;
+        match coin_price.checked_mul(usdt_price) {
+            Some(p) => markets[idx] = p,
+            None => error!("{} usd price overflow", coins[idx]),
+        }
+    }
+
+    // Coins pegged to another coin are resolved last, once the price they
+    // track is known.
+    let mut targets = Vec::new();
+    for idx in 0..size {
+        if idx == usdt_idx {
+            continue;
+        }
+
+        match get_peg(&coins[idx]) {
+            Some(peg) => match peg.reference {
+                peg::Reference::Value(_) => {
+                    result[idx] =
+                        apply_peg(&coins[idx], &peg, markets[idx], &result, tx)
+                            .await;
+                }
+                peg::Reference::Coin(_) => targets.push((idx, peg)),
+            },
+            None => result[idx] = markets[idx],
+        }
+    }
+
+    for (idx, peg) in targets.iter() {
+        result[*idx] =
+            apply_peg(&coins[*idx], peg, markets[*idx], &result, tx).await;
     }
 
     return Ok(result);
 }
 
-fn get_price_info(
-    prices: &MutexGuard<'_, Storage>,
-    index: usize,
-) -> (usize, f64) {
+fn is_derived(symbol: &str) -> bool {
+    CFG.derived
+        .as_ref()
+        .map_or(false, |derived| derived.contains_key(symbol))
+}
+
+/// Evaluates the derived coins and returns the inputs each one used.
+async fn get_derived_prices(
+    coins: &Vec<String>,
+    prices: &mut Vec<Decimal>,
+    tx: &mut Sender<Alarm>,
+) -> BTreeMap<String, BTreeMap<String, Decimal>> {
+    let mut inputs = BTreeMap::new();
+    let derived = match &CFG.derived {
+        Some(derived) => derived,
+        None => return inputs,
+    };
+
+    let pool = match CFG.rpcs.is_empty() {
+        true => None,
+        false => Some(get_rpc_pool(CFG.rpcs.clone()).await),
+    };
+    for idx in 0..coins.len() {
+        let cfg = match derived.get(&coins[idx]) {
+            Some(cfg) => cfg,
+            None => continue,
+        };
+
+        let rpc = pool.as_ref().and_then(|pool| pool.best());
+        match derived::evaluate(cfg, coins, prices, rpc.as_deref()).await {
+            Ok(evaluation) => {
+                info!(
+                    "derived {} = {} = {}, inputs {:?}",
+                    coins[idx],
+                    cfg.formula,
+                    evaluation.price,
+                    evaluation.inputs
+                );
+                prices[idx] = evaluation.price;
+                inputs.insert(coins[idx].clone(), evaluation.inputs);
+            }
+            Err(e) => {
+                let rpc_error = e.downcast_ref::<derived::RpcError>();
+                if let (Some(pool), Some(rpc_error)) = (&pool, rpc_error) {
+                    pool.mark_failed(&rpc_error.rpc);
+                }
+                error!("derive {} price failed: {:#}", coins[idx], e);
+                prices[idx] = Decimal::ZERO;
+                if CFG.enable_price_alarm {
+                    let alarm = mail::new_price_alarm(&format!(
+                        "Failed to derive {} price: {:#}",
+                        coins[idx], e
+                    ));
+                    _ = tx.send(alarm).await;
+                }
+            }
+        }
+    }
+    inputs
+}
+
+fn smooth_prices(
+    smoother: &mut Smoother,
+    coins: &Vec<String>,
+    prices: &mut Vec<Decimal>,
+    interval: u64,
+    now: u64,
+) {
+    let methods = match &CFG.smoothing {
+        Some(methods) => methods,
+        None => return,
+    };
+
+    for idx in 0..coins.len() {
+        if prices[idx] <= Decimal::ZERO {
+            continue;
+        }
+        if let Some(method) = methods.get(&coins[idx]) {
+            let smoothed =
+                smoother.apply(&coins[idx], method, now, prices[idx], interval);
+            info!("smoothed {}: {} -> {}", coins[idx], prices[idx], smoothed);
+            prices[idx] = smoothed;
+        }
+    }
+}
+
+async fn check_bounds(
+    smoother: &Smoother,
+    symbol: &str,
+    price: Decimal,
+    now: u64,
+    tx: &mut Sender<Alarm>,
+) -> bool {
+    let bounds = match CFG.bounds.as_ref().and_then(|b| b.get(symbol)) {
+        Some(bounds) => bounds,
+        None => return true,
+    };
+
+    let reference = smoother.reference_24h(symbol, now);
+    match bounds::check(price, bounds, reference) {
+        Some(violation) => {
+            error!("drop {} price {}: {}", symbol, price, violation);
+            if bounds::transition(symbol, true) && CFG.enable_price_alarm {
+                let alarm = mail::new_bounds_alarm(
+                    symbol,
+                    price,
+                    &violation.to_string(),
+                );
+                _ = tx.send(alarm).await;
+            }
+            false
+        }
+        None => {
+            bounds::transition(symbol, false);
+            true
+        }
+    }
+}
+
+/// Bounds-checks, smooths and scales the aggregated usd prices into the
+/// coin indexes and integer prices sent on-chain. Bounds apply to the raw
+/// aggregate, so a rejected price never enters the smoothing history.
+async fn get_publish_prices(
+    smoother: &mut Smoother,
+    coins: &Vec<String>,
+    prices: &mut Vec<Decimal>,
+    interval: u64,
+    now: u64,
+    tx: &mut Sender<Alarm>,
+) -> (Vec<u8>, Vec<u128>) {
+    for i in 0..coins.len() {
+        if prices[i] <= Decimal::ZERO {
+            continue;
+        }
+        if !check_bounds(smoother, &coins[i], prices[i], now, tx).await {
+            prices[i] = Decimal::ZERO;
+        }
+    }
+
+    smooth_prices(smoother, coins, prices, interval, now);
+
+    let mut coin_idxs = Vec::new();
+    let mut price_vals = Vec::new();
+
+    for i in 0..coins.len() {
+        if prices[i] <= Decimal::ZERO {
+            continue;
+        }
+
+        let price = match decimal::to_scaled(
+            prices[i],
+            CFG.decimals[i],
+            CFG.rounding.unwrap_or_default(),
+        ) {
+            Ok(price) => price,
+            Err(e) => {
+                error!("scale {} price failed: {}", coins[i], e);
+                continue;
+            }
+        };
+
+        smoother.record_published(&coins[i], now, prices[i]);
+        coin_idxs.push(i as u8);
+        price_vals.push(price);
+    }
+
+    (coin_idxs, price_vals)
+}
+
+/// Count and sum of the live quotes of coin `index`, none on overflow.
+fn get_price_info(prices: &Storage, index: usize) -> Option<(usize, Decimal)> {
     let mut coin_len = 0;
-    let mut coin_sum = 0.0f64;
+    let mut coin_sum = Decimal::ZERO;
     for i in 0..EXCHANGE_SIZE {
-        if prices.tickers[i].prices[index] > 0.0f64 {
+        if prices.tickers[i].prices[index] > Decimal::ZERO {
             coin_len = coin_len + 1;
-            coin_sum = coin_sum + prices.tickers[i].prices[index];
+            coin_sum = coin_sum.checked_add(prices.tickers[i].prices[index])?;
         }
     }
-    (coin_len, coin_sum)
+    Some((coin_len, coin_sum))
 }
 
 fn get_price_info_v2(
-    prices: &MutexGuard<'_, Storage>,
+    prices: &Storage,
     index: usize,
     _count: usize,
-) -> (Vec<f64>, Vec<f64>) {
-    let mut p = vec![0.0f64; EXCHANGE_SIZE];
-    let mut v = vec![0.0f64; EXCHANGE_SIZE];
+) -> (Vec<Decimal>, Vec<Decimal>, Vec<u64>) {
+    let mut p = vec![Decimal::ZERO; EXCHANGE_SIZE];
+    let mut v = vec![Decimal::ZERO; EXCHANGE_SIZE];
+    let mut t = vec![0u64; EXCHANGE_SIZE];
     for i in 0..EXCHANGE_SIZE {
-        if prices.tickers[i].prices[index] > 0.0f64 {
+        if prices.tickers[i].prices[index] > Decimal::ZERO {
             p[i] = prices.tickers[i].prices[index];
             v[i] = prices.tickers[i].volumes[index];
+            t[i] = prices.tickers[i].timestamps[index];
         }
     }
-    (p, v)
+    (p, v, t)
 }
 
 async fn handle_alarm_messages(mut rx: impl Stream<Item = Alarm> + Unpin) {
@@ -340,131 +799,439 @@ async fn handle_alarm_messages(mut rx: impl Stream<Item = Alarm> + Unpin) {
     }
 }
 
-fn get_sui_key() -> (String, String) {
+fn get_sui_key() -> (Zeroizing<String>, Zeroizing<String>) {
     let key = { SUIKEY.lock().unwrap().key.clone() };
     let mne = { SUIKEY.lock().unwrap().mnemonic.clone() };
 
-    (key, mne)
+    (Zeroizing::new(key), Zeroizing::new(mne))
+}
+
+fn get_key_scheme() -> Result<(SignatureScheme, Option<DerivationPath>)> {
+    let sk = SUIKEY.lock().unwrap();
+    let scheme = match sk.scheme.as_str() {
+        "" => SignatureScheme::ED25519,
+        name => mov::parse_scheme(name)?,
+    };
+    let path = match sk.derivation_path.as_str() {
+        "" => None,
+        path => Some(mov::parse_derivation_path(path, scheme)?),
+    };
+    Ok((scheme, path))
+}
+
+/// Whitespace normalized, so pasted mnemonics validate.
+fn strip_secret(input: &str) -> Zeroizing<String> {
+    Zeroizing::new(
+        input
+            .trim()
+            .split_inclusive(char::is_whitespace)
+            .filter(|part| !part.trim().is_empty())
+            .collect(),
+    )
+}
+
+/// Stores a base64 key or a mnemonic, false if it is neither.
+fn set_sui_key(secret: &str) -> bool {
+    if mov::utils::is_valid_base64_key(secret) {
+        SUIKEY.lock().unwrap().key = secret.to_owned();
+    } else if mov::utils::is_valid_mnemonic(secret) {
+        SUIKEY.lock().unwrap().mnemonic = secret.to_owned();
+    } else {
+        return false;
+    }
+    true
+}
+
+fn unlock_keystore(cfg: &KeystoreConfig) -> Result<()> {
+    let path = configuration::get_data_path(None, &cfg.path).unwrap();
+    let encrypted = keystore::EncryptedKey::read(Path::new(&path))?;
+    let passphrase = keystore::read_passphrase(
+        cfg.passphrase_file.as_deref(),
+        "Keystore passphrase: ",
+    )?;
+    let secret = encrypted.decrypt(passphrase.as_bytes())?;
+    if !set_sui_key(&secret) {
+        return Err(anyhow!("keystore holds no valid key or mnemonic"));
+    }
+    Ok(())
+}
+
+fn get_multisig() -> Result<Option<sink::sui::Multisig>> {
+    if !CFG.use_multi {
+        return Ok(None);
+    }
+    let members = cosign::members(&CFG.publickeys, &CFG.weights)?;
+    let mut cosigners = Vec::new();
+    for cfg in CFG.cosigners.iter().flatten() {
+        let cosigner = cosign::Cosigner::new(cfg)?;
+        let key = cosigner.publickey.as_ref();
+        if !members.iter().any(|(pk, _)| pk.as_ref() == key) {
+            return Err(anyhow!("cosigner {} is not in publickeys", cfg.name));
+        }
+        cosigners.push(cosigner);
+    }
+    let timeout = CFG.cosign_timeout.unwrap_or(cosign::DEFAULT_TIMEOUT);
+    Ok(Some(sink::sui::Multisig {
+        address: CFG.multi_address.clone(),
+        gas: CFG.gas.clone(),
+        publickeys: CFG.publickeys.clone(),
+        weights: CFG.weights.clone(),
+        threshold: CFG.threshold,
+        cosigners,
+        timeout: time::Duration::from_secs(timeout),
+    }))
+}
+
+async fn get_sui_sink(
+    name: &str,
+    target: sink::sui::Target,
+    pool: RpcPool,
+    treasury: &Option<Arc<Mutex<Treasury>>>,
+) -> Result<Box<dyn PriceSink>> {
+    let signer: Box<dyn Signer> = match &CFG.signer {
+        Some(cfg) => Box::new(RemoteSigner::new(cfg)?),
+        None => {
+            let (key, mne) = get_sui_key();
+            let (scheme, derivation_path) = get_key_scheme()?;
+            if !key.is_empty() {
+                Box::new(LocalSigner::from_key(&key)?)
+            } else {
+                Box::new(LocalSigner::from_mnemonic(
+                    &mne,
+                    scheme,
+                    derivation_path,
+                )?)
+            }
+        }
+    };
+    let mut wallet = Err(anyhow!("{}: no rpcs", name));
+    for rpc in pool.ranked() {
+        let chain_id = CFG.chain_id.clone();
+        wallet = mov::init_wallet(&rpc, chain_id, &signer.address()).await;
+        match &wallet {
+            Ok(_) => break,
+            Err(e) => {
+                error!("{}: rpc {}: {}", name, rpc, e);
+                pool.mark_failed(&rpc);
+            }
+        }
+    }
+    let wallet = wallet?;
+    let mut sink =
+        SuiSink::new(name, wallet, signer, target, get_multisig()?, pool)?;
+    if let Some(cfg) = &CFG.gas_pool {
+        sink = sink.with_gas_pool(cfg)?;
+    }
+    if let Some(treasury) = treasury {
+        // Top ups land as new coins, which a fixed gas coin never sees.
+        if CFG.use_multi && CFG.gas_pool.is_none() {
+            warn!("{}: multisig top ups need a gas_pool, skipped", name);
+        } else {
+            sink = sink.with_treasury(treasury.clone());
+        }
+    }
+    Ok(Box::new(sink))
+}
+
+fn get_evm_sink(cfg: &EvmSinkConfig) -> Result<Box<dyn PriceSink>> {
+    let key_env = cfg.key_env.as_deref().unwrap_or(sink::evm::DEFAULT_KEY_ENV);
+    let key = std::env::var(key_env)
+        .map_err(|_| anyhow!("{}: {} not set", cfg.name, key_env))?;
+    Ok(Box::new(EvmSink::new(cfg, &key)?))
+}
+
+fn get_starknet_sink(cfg: &StarknetSinkConfig) -> Result<Box<dyn PriceSink>> {
+    let key_env = cfg
+        .key_env
+        .as_deref()
+        .unwrap_or(sink::starknet::DEFAULT_KEY_ENV);
+    let key = std::env::var(key_env)
+        .map_err(|_| anyhow!("{}: {} not set", cfg.name, key_env))?;
+    Ok(Box::new(StarknetSink::new(cfg, &key)?))
+}
+
+/// Sui sinks and derived rates on the same rpcs share one pool, and so one
+/// prober.
+async fn get_rpc_pool(mut rpcs: Vec<String>) -> RpcPool {
+    rpcs.sort();
+    let mut pools = RPC_POOLS.lock().await;
+    if let Some(pool) = pools.get(&rpcs) {
+        return pool.clone();
+    }
+    let pool = RpcPool::new(&rpcs, CFG.chain_id.clone(), CFG.rpc_pool.as_ref());
+    pool.start().await;
+    pools.insert(rpcs, pool.clone());
+    pool
+}
+
+/// One treasury for all sui sinks, so they share its daily cap.
+fn get_treasury() -> Result<Option<Arc<Mutex<Treasury>>>> {
+    let cfg = match &CFG.top_up {
+        Some(cfg) => cfg,
+        None => return Ok(None),
+    };
+    let path = data_path(cfg.log_file.as_deref().unwrap_or(TOP_UP_LOG_FILE))
+        .ok_or(anyhow!("no data path for the top up log"))?;
+    let treasury = Treasury::new(cfg, CFG.balance, &path)?;
+    Ok(Some(Arc::new(Mutex::new(treasury))))
 }
 
-fn get_nex_rpc() -> String {
-    let mut idx = RPCINDEX.lock().unwrap();
-    *idx += 1;
+async fn get_sinks() -> Vec<Box<dyn PriceSink>> {
+    let treasury = match get_treasury() {
+        Ok(treasury) => treasury,
+        Err(e) => {
+            error!("top up: {}", e);
+            process::exit(1);
+        }
+    };
+    let configs = match &CFG.sinks {
+        Some(configs) => configs,
+        None => {
+            let target = sink::sui::Target {
+                package_id: CFG.package_id.clone(),
+                module: sink::sui::DEFAULT_MODULE.to_owned(),
+                function: sink::sui::DEFAULT_FUNCTION.to_owned(),
+                oracle_cap: CFG.oracle_cap.clone(),
+                price_oracle: CFG.price_oracle.clone(),
+                gas_budget: CFG.gas_budget,
+                gas_price: None,
+                dry_run: CFG.dry_run.clone(),
+                extra_oracles: vec![],
+                prices_path: CFG.prices_path.clone(),
+                timestamps_path: None,
+                verify: false,
+            };
+            let pool = get_rpc_pool(CFG.rpcs.clone()).await;
+            let sink = get_sui_sink(&CFG.account, target, pool, &treasury)
+                .await
+                .unwrap();
+            return vec![sink];
+        }
+    };
 
-    let i = (*idx as usize) % CFG.rpcs.len();
-    println!("rpc index: {}", i);
-    CFG.rpcs[i].clone()
+    let mut sinks = Vec::new();
+    for config in configs.iter() {
+        let result = match config {
+            SinkConfig::Sui(c) => {
+                let target = sink::sui::Target {
+                    package_id: c.package_id.clone(),
+                    module: c
+                        .module
+                        .clone()
+                        .unwrap_or(sink::sui::DEFAULT_MODULE.to_owned()),
+                    function: c
+                        .function
+                        .clone()
+                        .unwrap_or(sink::sui::DEFAULT_FUNCTION.to_owned()),
+                    oracle_cap: c.oracle_cap.clone(),
+                    price_oracle: c.price_oracle.clone(),
+                    gas_budget: c.gas_budget.unwrap_or(CFG.gas_budget),
+                    gas_price: c.gas_price,
+                    dry_run: c.dry_run.clone().or(CFG.dry_run.clone()),
+                    extra_oracles: c
+                        .extra_oracles
+                        .iter()
+                        .flatten()
+                        .map(|o| (o.oracle_cap.clone(), o.price_oracle.clone()))
+                        .collect(),
+                    prices_path: c.prices_path.clone(),
+                    timestamps_path: c.timestamps_path.clone(),
+                    verify: c.verify.unwrap_or(false),
+                };
+                let rpcs = c.rpcs.clone().unwrap_or(CFG.rpcs.clone());
+                let pool = get_rpc_pool(rpcs).await;
+                get_sui_sink(&c.name, target, pool, &treasury).await
+            }
+            SinkConfig::Evm(c) => get_evm_sink(c),
+            SinkConfig::Starknet(c) => get_starknet_sink(c),
+        };
+        match result {
+            Ok(sink) => sinks.push(sink),
+            Err(e) => error!("sink init failed: {}", e),
+        }
+    }
+    sinks
+}
+
+/// Sinks without `balance_alarms` warn below `balance` base units of their
+/// own coin, which is only meaningful for sui sinks.
+fn get_balance_monitor(sink: &dyn PriceSink) -> balance::Monitor {
+    let alarms = CFG.balance_alarms.as_ref();
+    let thresholds = match alarms.and_then(|a| a.get(sink.name())) {
+        Some(thresholds) => thresholds.clone(),
+        None => {
+            let decimals = sink.balance_decimals();
+            if decimals != sink::sui::SUI_DECIMALS {
+                warn!(
+                    "{}: no balance_alarms entry, warning below {} base units",
+                    sink.name(),
+                    CFG.balance
+                );
+            }
+            balance::Thresholds {
+                warning: Some(CFG.balance as f64 / 10f64.powi(decimals as i32)),
+                critical: None,
+                hysteresis: None,
+                runway_window: None,
+            }
+        }
+    };
+    balance::Monitor::new(thresholds)
 }
 
 async fn handle_price_messages(
-    mut rx: impl Stream<Item = (Vec<u8>, Vec<u64>, u64)> + Unpin,
+    mut rx: impl Stream<Item = Batch> + Unpin,
+    mut tx: Sender<Alarm>,
 ) {
-    let sui_config_path = configuration::get_sui_config_path().unwrap();
-    let (key, mne) = get_sui_key();
-    let mut wallet = mov::init_wallet(&sui_config_path, &key, &mne)
-        .await
-        .unwrap();
+    let mut sinks = get_sinks().await;
+    // The signers hold the key from here on.
+    SUIKEY.lock().unwrap().clear();
+    log_last_prices(&mut sinks).await;
+    let mut monitors: Vec<balance::Monitor> = sinks
+        .iter()
+        .map(|s| get_balance_monitor(s.as_ref()))
+        .collect();
     let mut check_balance_ts = misc::get_timestamp();
-    while let Some(price) = rx.recv().await {
+    while let Some(batch) = rx.recv().await {
         let current_timestamp = misc::get_timestamp();
         if current_timestamp - check_balance_ts > CFG.check_balance_interval {
-            check_balance(&mut wallet).await;
+            for (sink, monitor) in sinks.iter_mut().zip(monitors.iter_mut()) {
+                check_balance(sink, monitor, &mut tx).await;
+                top_up(sink, &mut tx).await;
+            }
             check_balance_ts = current_timestamp;
         }
 
-        if current_timestamp - price.2 > CFG.interval {
+        if current_timestamp - batch.ts > CFG.interval * 1000 {
             continue;
         }
 
-        let json_params = mov::pack_params(
-            &CFG.oracle_cap,
-            &CFG.price_oracle,
-            &price.0,
-            &price.1,
-        )
-        .unwrap();
-
-        if !send_tx(&mut wallet, json_params.clone(), &price.1).await {
-            let _ = wallet.set_client(get_nex_rpc());
-            send_tx(&mut wallet, json_params, &price.1).await;
+        for sink in sinks.iter_mut() {
+            let digest = match sink.publish(&batch).await {
+                Ok(digest) => {
+                    info!(
+                        "ok, {} set prices {:?}, digest {}",
+                        sink.name(),
+                        batch.prices,
+                        digest
+                    );
+                    Some(digest)
+                }
+                Err(e) => match e.downcast_ref::<VerifyError>() {
+                    Some(v) => {
+                        error!("{} verify failed: {}", sink.name(), v);
+                        let alarm = mail::new_verify_alarm(sink.name(), v);
+                        _ = tx.send(alarm).await;
+                        Some(v.digest.clone())
+                    }
+                    None => {
+                        error!("{} publish failed: {}", sink.name(), e);
+                        None
+                    }
+                },
+            };
+            record(Entry::Tx(TxRecord {
+                round: batch.round,
+                ts: misc::get_timestamp(),
+                sink: sink.name().to_owned(),
+                digest,
+            }));
         }
     }
 }
 
-async fn send_tx(
-    wallet: &mut WalletContext,
-    json_params_v3: Vec<SuiJsonValue>,
-    price: &Vec<u64>,
-) -> bool {
-    if !CFG.use_multi {
-        match mov::call(
-            wallet,
-            &CFG.package_id,
-            &ORACLE_MODULE.to_string(),
-            &ORACLE_FEED_FUNCTION.to_string(),
-            CFG.gas_budget,
-            json_params,
-        )
-        .await
-        {
-            Ok(_) => info!("ok, set prices {:?}", price),
-            Err(e) => {
-                error!("call: {}", e);
-                return false;
-            }
-        };
-    } else {
-        match mov::multi_call(
-            wallet,
-            &CFG.package_id,
-            &ORACLE_MODULE.to_string(),
-            &ORACLE_FEED_FUNCTION.to_string(),
-            &CFG.gas,
-            CFG.gas_budget,
-            json_params,
-            &CFG.publickeys,
-            &CFG.weights,
-            CFG.threshold,
-        )
-        .await
-        {
-            Ok(_) => info!("ok, set prices {:?}", price),
-            Err(e) => {
-                error!("multi call: {}", e);
-                return false;
-            }
-        };
-    }
-
-    true
-}
-
-async fn check_balance(wallet: &mut WalletContext) {
+async fn check_balance(
+    sink: &mut Box<dyn PriceSink>,
+    monitor: &mut balance::Monitor,
+    tx: &mut Sender<Alarm>,
+) {
     if !CFG.enable_balance_alarm {
         return;
     }
-    let balance;
-    if !CFG.use_multi {
-        balance = mov::get_balance(wallet).await;
-    } else {
-        balance =
-            mov::get_multi_balance(wallet, &CFG.multi_address, &CFG.gas).await;
+
+    let v = match sink.balance().await {
+        Ok(balance) => balance,
+        Err(e) => {
+            error!("get {} balance failed: {}", sink.name(), e);
+            return;
+        }
+    };
+
+    let fv = v as f64 / 10f64.powi(sink.balance_decimals() as i32);
+    let account = sink.name().to_owned();
+    PROM.push(fv, &CFG.ip, &CFG.env, &account, &fv.to_string());
+
+    if let Some(level) = monitor.observe(misc::get_timestamp(), fv) {
+        let runway = monitor.runway_days();
+        warn!(
+            "{} balance {} is {}, runway {:?} days",
+            account, fv, level, runway
+        );
+        let alarm = mail::new_balance_alarm(
+            &account,
+            level,
+            fv,
+            monitor.threshold(level),
+            runway,
+        );
+        _ = tx.send(alarm).await;
     }
+}
 
-    let mut v = 0u64;
-    match balance {
-        Ok(balance) => {
-            v = balance;
+/// Reads back what each sink holds on-chain, so a target that cannot be
+/// read shows at startup rather than on the first verify.
+async fn log_last_prices(sinks: &mut Vec<Box<dyn PriceSink>>) {
+    let coin_idxs: Vec<u8> = (0..CFG.coins.len() as u8).collect();
+    for sink in sinks.iter_mut() {
+        match sink.last_prices(&coin_idxs).await {
+            Ok(prices) => {
+                info!("{} on-chain prices {:?}", sink.name(), prices)
+            }
+            Err(e) => warn!("{} on-chain prices: {}", sink.name(), e),
         }
-        Err(e) => error!("get balance failed: {}", e),
     }
+}
 
-    if v == 0 {
-        return;
+/// Pushes the rpc pool metrics every probe interval, whether or not the
+/// balance alarm is on.
+async fn push_rpc_metrics() {
+    let secs = CFG
+        .rpc_pool
+        .as_ref()
+        .and_then(|c| c.probe_interval)
+        .unwrap_or(mov::rpc::DEFAULT_PROBE_INTERVAL);
+    let mut interval = time::interval(time::Duration::from_secs(secs));
+    loop {
+        interval.tick().await;
+        // The push is a blocking request.
+        let push = || PROM.push_rpc(&CFG.ip, &CFG.env);
+        if let Err(e) = tokio::task::spawn_blocking(push).await {
+            error!("push rpc metrics: {}", e);
+        }
     }
+}
+
+async fn top_up(sink: &mut Box<dyn PriceSink>, tx: &mut Sender<Alarm>) {
+    let transfer = match sink.top_up().await {
+        Ok(Some(transfer)) => transfer,
+        Ok(None) => return,
+        Err(e) => {
+            error!("{} top up failed: {}", sink.name(), e);
+            return;
+        }
+    };
 
-    let fv = v as f64 / 1000000000.0;
-    PROM.push(fv, &CFG.ip, &CFG.env, &CFG.account, &fv.to_string());
+    let amount =
+        transfer.amount as f64 / 10f64.powi(sink.balance_decimals() as i32);
+    warn!(
+        "{} topped up with {}, tx {:?}",
+        sink.name(),
+        amount,
+        transfer.digest
+    );
+    let alarm =
+        mail::new_top_up_alarm(sink.name(), amount, transfer.digest.as_deref());
+    _ = tx.send(alarm).await;
 }
 
 fn set_signal_handler(r: Arc<AtomicBool>) {
@@ -484,26 +1251,19 @@ fn set_signal_handler(r: Arc<AtomicBool>) {
 fn interactive() {
     if CFG.interactive {
         loop {
-            println!("Please enter a private key or mnemonic:");
-            let mut input = String::new();
-            if io::stdin().read_line(&mut input).is_err() {
-                continue;
-            }
-
-            let strip_input: String = input
-                .trim()
-                .split_inclusive(char::is_whitespace)
-                .filter(|part| !part.trim().is_empty())
-                .collect();
+            let input = match rpassword::prompt_password(
+                "Please enter a private key or mnemonic: ",
+            ) {
+                Ok(input) => Zeroizing::new(input),
+                Err(_) => continue,
+            };
+
+            let strip_input = strip_secret(&input);
             if strip_input.is_empty() {
                 continue;
             }
 
-            if mov::utils::is_valid_base64_key(&strip_input) {
-                SUIKEY.lock().unwrap().key = strip_input;
-            } else if mov::utils::is_valid_mnemonic(&strip_input) {
-                SUIKEY.lock().unwrap().mnemonic = strip_input;
-            } else {
+            if !set_sui_key(&strip_input) {
                 println!("The input format is incorrect!");
                 continue;
             }
@@ -512,6 +1272,37 @@ fn interactive() {
     }
 }
 
+/// Picks the scheme and path from the command line, then tiny.yaml, and
+/// checks a given key was made for that scheme.
+fn parse_key_scheme(args: &Cli) -> Result<()> {
+    let scheme = match args.scheme.as_str() {
+        "" => CFG.key_scheme.clone().unwrap_or_default(),
+        scheme => scheme.to_owned(),
+    };
+    let derivation_path = match args.derivation_path.as_str() {
+        "" => CFG.derivation_path.clone().unwrap_or_default(),
+        path => path.to_owned(),
+    };
+    {
+        let mut sk = SUIKEY.lock().unwrap();
+        sk.scheme = scheme;
+        sk.derivation_path = derivation_path;
+    }
+
+    let (scheme, _) = get_key_scheme()?;
+    let (key, _) = get_sui_key();
+    if let Some(flag) = mov::utils::key_flag(&key) {
+        if flag != scheme.flag() {
+            return Err(anyhow!(
+                "key flag {} does not match {:?}",
+                flag,
+                scheme
+            ));
+        }
+    }
+    Ok(())
+}
+
 fn parse_args(args: &Cli) {
     let opt_key = std::env::var_os("KEY");
     if opt_key.is_some() {
@@ -532,15 +1323,187 @@ fn parse_args(args: &Cli) {
         SUIKEY.lock().unwrap().mnemonic = args.mnemonic.clone();
     }
 
+    if let Err(e) = get_primaries() {
+        eprintln!("consensus: {}", e);
+        process::exit(1);
+    }
+
     let (key, mnemonic) = get_sui_key();
-    if &key.len() == &0 && &mnemonic.len() == &0 {
+    if key.is_empty() && mnemonic.is_empty() {
+        if let Some(cfg) = &CFG.keystore {
+            if let Err(e) = unlock_keystore(cfg) {
+                eprintln!("unlock keystore failed: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    if let Err(e) = parse_key_scheme(args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+
+    let (key, mnemonic) = get_sui_key();
+    if &key.len() == &0 && &mnemonic.len() == &0 && CFG.signer.is_none() {
         eprintln!("key or mnemonic missing");
         process::exit(0);
     }
 }
 
+#[derive(Subcommand, Debug)]
+enum KeyAction {
+    /// Encrypts a private key or mnemonic, read without echo, into the
+    /// keystore.
+    Import {
+        #[clap(short, long)]
+        /// Keystore file. Defaults to `keystore.path` of tiny.yaml.
+        path: Option<String>,
+
+        #[clap(long)]
+        /// Read the passphrase from this file instead of prompting.
+        passphrase_file: Option<String>,
+    },
+    /// Prints the decrypted key or mnemonic. Refuses when stdout is not a
+    /// terminal unless `--yes` is given.
+    Export {
+        #[clap(short, long)]
+        /// Keystore file. Defaults to `keystore.path` of tiny.yaml.
+        path: Option<String>,
+
+        #[clap(long)]
+        /// Read the passphrase from this file instead of prompting.
+        passphrase_file: Option<String>,
+
+        #[clap(long)]
+        /// Print the secret even if stdout is redirected or piped.
+        yes: bool,
+    },
+}
+
+fn get_keystore_path(path: &Option<String>) -> Result<String> {
+    let path = match path {
+        Some(path) => path.clone(),
+        None => CFG
+            .keystore
+            .as_ref()
+            .map(|k| configuration::get_data_path(None, &k.path).unwrap())
+            .ok_or(anyhow!("no keystore path given or configured"))?,
+    };
+    Ok(path)
+}
+
+fn key_command(action: &KeyAction) -> Result<()> {
+    match action {
+        KeyAction::Import {
+            path,
+            passphrase_file,
+        } => {
+            let path = get_keystore_path(path)?;
+            let input = Zeroizing::new(rpassword::prompt_password(
+                "Private key or mnemonic: ",
+            )?);
+            let secret = strip_secret(&input);
+            if !mov::utils::is_valid_base64_key(&secret)
+                && !mov::utils::is_valid_mnemonic(&secret)
+            {
+                return Err(anyhow!("not a base64 key or mnemonic"));
+            }
+
+            let passphrase = keystore::read_passphrase(
+                passphrase_file.as_deref(),
+                "New passphrase: ",
+            )?;
+            if passphrase_file.is_none() {
+                let repeated =
+                    keystore::read_passphrase(None, "Repeat passphrase: ")?;
+                if passphrase != repeated {
+                    return Err(anyhow!("passphrases differ"));
+                }
+            }
+            let encrypted = keystore::EncryptedKey::encrypt(
+                secret.as_bytes(),
+                passphrase.as_bytes(),
+            )?;
+            encrypted.write(Path::new(&path))?;
+            println!("key stored in {}", path);
+        }
+        KeyAction::Export {
+            path,
+            passphrase_file,
+            yes,
+        } => {
+            if !yes && !std::io::stdout().is_terminal() {
+                return Err(anyhow!(
+                    "stdout is not a terminal, pass --yes to print the secret"
+                ));
+            }
+            let path = get_keystore_path(path)?;
+            let encrypted = keystore::EncryptedKey::read(Path::new(&path))?;
+            let passphrase = keystore::read_passphrase(
+                passphrase_file.as_deref(),
+                "Passphrase: ",
+            )?;
+            let secret = encrypted.decrypt(passphrase.as_bytes())?;
+            println!("{}", secret.as_str());
+        }
+    }
+    Ok(())
+}
+
 fn main() {
     let args = Cli::parse();
+    if let Some(Command::Key { action }) = &args.command {
+        if let Err(e) = key_command(action) {
+            eprintln!("key failed: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Replay { path, config }) = &args.command {
+        if let Some(config) = config {
+            std::env::set_var(configuration::CONFIG_ENV, config);
+        }
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                if let Err(e) = replay(path).await {
+                    eprintln!("replay failed: {}", e);
+                    process::exit(1);
+                }
+            });
+        return;
+    }
+    if let Some(Command::SignerStub { listen }) = &args.command {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                if let Err(e) = signer_stub(listen).await {
+                    eprintln!("signer stub failed: {}", e);
+                    process::exit(1);
+                }
+            });
+        return;
+    }
+    if let Some(Command::Cosign { config }) = &args.command {
+        if let Some(config) = config {
+            std::env::set_var(configuration::CONFIG_ENV, config);
+        }
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                if let Err(e) = cosign().await {
+                    eprintln!("cosign failed: {}", e);
+                    process::exit(1);
+                }
+            });
+        return;
+    }
+
     interactive();
 
     parse_args(&args);
@@ -558,6 +1521,286 @@ fn main() {
         })
 }
 
+/// Runs recorded snapshots through the same aggregation, smoothing and bounds
+/// as the live loop. Quotes are matched to the configured coins by symbol.
+/// Derived coins are skipped since their rates are not part of the snapshot,
+/// and alarms are dropped.
+async fn replay(path: &str) -> Result<()> {
+    get_primaries()?;
+    let snapshots = replay::read_snapshots(path)?;
+    println!("replaying {} snapshots from {}", snapshots.len(), path);
+
+    let (mut tx, mut rx) = broadcast::channel::<mail::Alarm>(100);
+    tokio::task::spawn(async move { while rx.recv().await.is_some() {} });
+
+    let mut smoother = Smoother::default();
+    let mut report = replay::Report::default();
+
+    let mut missing = HashSet::new();
+    for snapshot in snapshots.iter() {
+        let (snapshot, lacks) = replay::align(snapshot, &CFG.coins);
+        for coin in lacks {
+            if missing.insert(coin.clone()) {
+                eprintln!("round {}: no quotes for {}", snapshot.round, coin);
+            }
+        }
+        let coins = &snapshot.coins;
+        let ts = snapshot.ts;
+        report.rounds += 1;
+
+        let mut prices =
+            match aggregate_prices(coins, &snapshot.storage, ts, &mut tx).await
+            {
+                Ok(prices) => prices,
+                Err(e) => {
+                    eprintln!("round {}: {}", snapshot.round, e);
+                    coins.iter().for_each(|c| report.skip(c));
+                    continue;
+                }
+            };
+
+        let (coin_idxs, _) = get_publish_prices(
+            &mut smoother,
+            coins,
+            &mut prices,
+            CFG.interval,
+            ts,
+            &mut tx,
+        )
+        .await;
+
+        let usdt_price = match coins.iter().position(|c| c == "USDT") {
+            Some(idx) if !prices[idx].is_zero() => prices[idx],
+            _ => Decimal::ONE,
+        };
+        for (i, coin) in coins.iter().enumerate() {
+            if coin_idxs.contains(&(i as u8)) {
+                let median =
+                    replay::source_median(&snapshot.storage, i, usdt_price);
+                report.publish(coin, prices[i], median);
+            } else {
+                report.skip(coin);
+            }
+        }
+
+        if !coin_idxs.is_empty() {
+            report.transaction(CFG.gas_budget);
+        }
+    }
+
+    report.print();
+    Ok(())
+}
+
+/// The stub signs anything it is sent, so it may only listen on a unix
+/// socket or a loopback address.
+async fn check_stub_listen(listen: &str) -> Result<()> {
+    if listen.starts_with(cosign::UNIX_PREFIX) {
+        return Ok(());
+    }
+    let addrs: Vec<_> = tokio::net::lookup_host(listen).await?.collect();
+    if addrs.is_empty() || addrs.iter().any(|a| !a.ip().is_loopback()) {
+        return Err(anyhow!("{} is not unix: or a loopback address", listen));
+    }
+    Ok(())
+}
+
+async fn signer_stub(listen: &str) -> Result<()> {
+    check_stub_listen(listen).await?;
+    let key = std::env::var(SIGNER_KEY_ENV)
+        .map_err(|_| anyhow!("{} not set", SIGNER_KEY_ENV))?;
+    let keypair = SuiKeyPair::decode_base64(&key)
+        .map_err(|e| anyhow!("{}: {}", SIGNER_KEY_ENV, e))?;
+    let listener = cosign::server::Listener::bind(listen).await?;
+    println!(
+        "signing as {} on {}",
+        SuiAddress::from(&keypair.public()),
+        listen
+    );
+
+    loop {
+        let mut conn = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("accept: {}", e);
+                continue;
+            }
+        };
+        let response = match conn.read_request().await {
+            Ok(request) => {
+                let signature =
+                    mov::partial_signature(&request.tx_data, &keypair);
+                cosign::CosignResponse {
+                    signature: Some(signature.encode_base64()),
+                    error: None,
+                }
+            }
+            Err(e) => cosign::CosignResponse {
+                signature: None,
+                error: Some(e.to_string()),
+            },
+        };
+        if let Err(e) = conn.respond(&response).await {
+            eprintln!("respond: {}", e);
+        }
+    }
+}
+
+/// The co-signer's own scaled prices, indexed by coin.
+async fn get_own_prices(tx: &mut Sender<Alarm>) -> Vec<Option<u128>> {
+    let coins = CFG.coins.clone();
+    let storage = fetch_prices(&coins).await;
+    let now = misc::get_timestamp();
+    let mut prices = match aggregate_prices(&coins, &storage, now, tx).await {
+        Ok(prices) => prices,
+        Err(e) => {
+            error!("own prices: {}", e);
+            return vec![];
+        }
+    };
+    get_derived_prices(&coins, &mut prices, tx).await;
+
+    let rounding = CFG.rounding.unwrap_or_default();
+    prices
+        .iter()
+        .enumerate()
+        .map(|(i, price)| {
+            if *price <= Decimal::ZERO {
+                return None;
+            }
+            decimal::to_scaled(*price, CFG.decimals[i], rounding).ok()
+        })
+        .collect()
+}
+
+fn get_policy(cfg: &CosignConfig) -> Result<cosign::policy::Policy> {
+    let package_id = cfg.package_id.as_ref().unwrap_or(&CFG.package_id);
+    Ok(cosign::policy::Policy {
+        package: ObjectID::from_hex_literal(package_id)?,
+        module: cfg
+            .module
+            .clone()
+            .unwrap_or(sink::sui::DEFAULT_MODULE.to_owned()),
+        function: cfg
+            .function
+            .clone()
+            .unwrap_or(sink::sui::DEFAULT_FUNCTION.to_owned()),
+        max_gas_budget: cfg.max_gas_budget.unwrap_or(CFG.gas_budget),
+        max_age: cfg.max_age.unwrap_or(2 * CFG.interval) * 1000,
+        tolerance_bps: cfg.tolerance_bps,
+    })
+}
+
+/// Answers each co-signing connection in its own task. Own prices are
+/// fetched at most once per interval and shared between them.
+async fn cosign() -> Result<()> {
+    dotenv().ok();
+    logger::init_logger(true, CFG.log_cfg, CFG.data_dir.as_deref());
+
+    let cfg = CFG
+        .cosign
+        .as_ref()
+        .ok_or(anyhow!("cosign not configured"))?;
+    get_primaries()?;
+    let key_env = cfg.key_env.as_deref().unwrap_or(cosign::DEFAULT_KEY_ENV);
+    let key =
+        std::env::var(key_env).map_err(|_| anyhow!("{} not set", key_env))?;
+    let keypair = SuiKeyPair::decode_base64(&key)
+        .map_err(|e| anyhow!("{}: {}", key_env, e))?;
+    let policy = get_policy(cfg)?;
+
+    let (mut tx, mut rx) = broadcast::channel::<mail::Alarm>(100);
+    tokio::task::spawn(async move { while rx.recv().await.is_some() {} });
+
+    let listener = cosign::server::Listener::bind(&cfg.listen).await?;
+    info!(
+        "cosigning as {} on {}",
+        SuiAddress::from(&keypair.public()),
+        cfg.listen
+    );
+
+    let keypair = Arc::new(keypair);
+    let policy = Arc::new(policy);
+    let own = Arc::new(Mutex::new(OwnPrices::default()));
+    loop {
+        let conn = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("accept: {}", e);
+                continue;
+            }
+        };
+        // A slow or silent peer must not hold up the other feeders.
+        tokio::spawn(handle_cosign(
+            conn,
+            keypair.clone(),
+            policy.clone(),
+            own.clone(),
+            tx.clone(),
+        ));
+    }
+}
+
+/// The co-signer's own scaled prices, refreshed once per interval.
+#[derive(Default)]
+struct OwnPrices {
+    prices: Vec<Option<u128>>,
+    ts: u64,
+}
+
+async fn handle_cosign(
+    mut conn: cosign::server::Connection,
+    keypair: Arc<SuiKeyPair>,
+    policy: Arc<cosign::policy::Policy>,
+    own: Arc<Mutex<OwnPrices>>,
+    mut tx: Sender<Alarm>,
+) {
+    let request = match conn.read_request().await {
+        Ok(request) => request,
+        Err(e) => {
+            error!("read request: {}", e);
+            return;
+        }
+    };
+
+    let now = misc::get_timestamp();
+    let checked = match policy.check_calls(&request.tx_data, now) {
+        Ok(updates) if updates.is_empty() => Ok(()),
+        Ok(updates) => {
+            let mut own = own.lock().await;
+            if now.saturating_sub(own.ts) > CFG.interval * 1000 {
+                own.prices = get_own_prices(&mut tx).await;
+                own.ts = now;
+            }
+            updates
+                .iter()
+                .try_for_each(|u| policy.check_prices(u, &own.prices))
+        }
+        Err(e) => Err(e),
+    };
+
+    let response = match checked {
+        Ok(()) => {
+            let signature = mov::partial_signature(&request.tx_data, &keypair);
+            info!("signed {}", request.tx_data.digest());
+            cosign::CosignResponse {
+                signature: Some(signature.encode_base64()),
+                error: None,
+            }
+        }
+        Err(e) => {
+            warn!("refused {}: {}", request.tx_data.digest(), e);
+            cosign::CosignResponse {
+                signature: None,
+                error: Some(e.to_string()),
+            }
+        }
+    };
+    if let Err(e) = conn.respond(&response).await {
+        error!("respond: {}", e);
+    }
+}
+
 async fn _main(args: &Cli) -> Result<()> {
     dotenv().ok();
 
@@ -569,14 +1812,15 @@ async fn _main(args: &Cli) -> Result<()> {
         }
     }
 
-    logger::init_logger(true, CFG.log_cfg);
+    logger::init_logger(true, CFG.log_cfg, CFG.data_dir.as_deref());
     info!("tinyd started");
 
     let (mut tx, rx) = broadcast::channel::<mail::Alarm>(100);
     tokio::task::spawn(handle_alarm_messages(rx));
 
-    let (mut tx2, rx2) = broadcast::channel::<(Vec<u8>, Vec<u64>, u64)>(100);
-    tokio::task::spawn(handle_price_messages(rx2));
+    let (mut tx2, rx2) = broadcast::channel::<Batch>(100);
+    tokio::task::spawn(handle_price_messages(rx2, tx.clone()));
+    tokio::task::spawn(push_rpc_metrics());
 
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
@@ -589,16 +1833,27 @@ async fn _main(args: &Cli) -> Result<()> {
     }
 
     warn!("interval: {}", interval);
+    let round_interval = interval;
     warn!("coins: {:?}", CFG.coins);
     warn!("imitations: {:?}", CFG.imitations);
+    warn!("pegs: {:?}", CFG.pegs);
+    warn!("derived: {:?}", CFG.derived);
+    warn!("bounds: {:?}", CFG.bounds);
+    warn!("primaries: {:?}", *PRIMARIES);
+
+    let history_path =
+        data_path(CFG.history_file.as_deref().unwrap_or(HISTORY_FILE)).unwrap();
+    let mut smoother = Smoother::load(&history_path);
 
     let mut interval = time::interval(time::Duration::from_secs(interval));
     loop {
         interval.tick().await;
 
         let coins = CFG.coins.clone();
-        let result = get_prices(&coins).await;
-        let prices = match result {
+        let storage = fetch_prices(&coins).await;
+        let now = misc::get_timestamp();
+        let result = aggregate_prices(&coins, &storage, now, &mut tx).await;
+        let mut prices = match result {
             Ok(res) => res,
             Err(err) => {
                 error!("error {:?}", err);
@@ -609,6 +1864,16 @@ async fn _main(args: &Cli) -> Result<()> {
         warn!("get coins prices: {:#?}", prices);
 
         if prices.len() == 0 {
+            record(Entry::Round(RoundRecord {
+                round: now,
+                ts: now,
+                coins,
+                storage,
+                aggregated: prices,
+                derived: BTreeMap::new(),
+                coin_idxs: vec![],
+                price_vals: vec![],
+            }));
             if CFG.enable_price_alarm {
                 let alarm =
                     mail::new_price_alarm("Failed to obtain currency price!");
@@ -617,27 +1882,45 @@ async fn _main(args: &Cli) -> Result<()> {
             continue;
         }
 
-        let mut coin_idxs = Vec::new();
-        let mut price_vals = Vec::new();
+        let derived = get_derived_prices(&coins, &mut prices, &mut tx).await;
+        let aggregated = prices.clone();
+        let (coin_idxs, price_vals) = get_publish_prices(
+            &mut smoother,
+            &coins,
+            &mut prices,
+            round_interval,
+            now,
+            &mut tx,
+        )
+        .await;
 
-        for i in 0..coins.len() {
-            if prices[i] <= 0.0f64 {
-                continue;
+        if CFG.smoothing.is_some() || CFG.bounds.is_some() {
+            if let Err(e) = smoother.save() {
+                error!("save history failed: {}", e);
             }
-
-            let dec = (10u32.pow(CFG.decimals[i] as u32)) as u64;
-            let price = prices[i] * dec as f64;
-            let price = price as u64;
-
-            coin_idxs.push(i as u8);
-            price_vals.push(price);
         }
 
         warn!("set coin idxs: {:#?}", coin_idxs);
         warn!("set coins prices: {:?}", price_vals);
 
+        record(Entry::Round(RoundRecord {
+            round: now,
+            ts: now,
+            coins,
+            storage,
+            aggregated,
+            derived,
+            coin_idxs: coin_idxs.clone(),
+            price_vals: price_vals.clone(),
+        }));
+
         _ = tx2
-            .send((coin_idxs, price_vals, misc::get_timestamp()))
+            .send(Batch {
+                round: now,
+                ts: now,
+                coin_idxs,
+                prices: price_vals,
+            })
             .await;
 
         count += 1;
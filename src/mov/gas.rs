@@ -0,0 +1,150 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use sui_sdk::types::base_types::SuiAddress;
+use sui_types::base_types::{ObjectID, ObjectRef};
+use sui_types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+use sui_types::transaction::{
+    Argument, Command, ObjectArg, ProgrammableTransaction,
+};
+
+use super::wallet::Wallet;
+use crate::configuration::GasPoolConfig;
+use crate::misc;
+
+#[derive(Debug, Clone)]
+pub struct Coin {
+    pub balance: u64,
+    pub object_ref: ObjectRef,
+}
+
+impl Coin {
+    pub fn id(&self) -> ObjectID {
+        self.object_ref.0
+    }
+}
+
+/// Keeps the gas coins of one address: `size` coins of about `coin_balance`
+/// each for submissions, the largest coin as the reserve they are split
+/// from, and dust merged back into the reserve.
+#[derive(Debug)]
+pub struct GasPool {
+    pub owner: SuiAddress,
+    size: usize,
+    coin_balance: u64,
+    dust: u64,
+    maintain_interval: u64,
+    last_maintain: u64,
+    coins: Vec<Coin>,
+    locked: HashSet<ObjectID>,
+}
+
+impl GasPool {
+    pub fn new(owner: SuiAddress, cfg: &GasPoolConfig) -> GasPool {
+        GasPool {
+            owner,
+            size: cfg.size.max(1),
+            coin_balance: cfg.coin_balance,
+            dust: cfg.dust,
+            maintain_interval: cfg.maintain_interval * 1000,
+            last_maintain: 0,
+            coins: Vec::new(),
+            locked: HashSet::new(),
+        }
+    }
+
+    pub async fn refresh(&mut self, wallet: &Wallet) -> Result<()> {
+        let coins = wallet.gas_objects(self.owner).await?;
+        self.coins = coins
+            .into_iter()
+            .map(|(balance, coin)| Coin {
+                balance,
+                object_ref: coin.object_ref(),
+            })
+            .collect();
+        self.coins.sort_by_key(|c| c.balance);
+        let ids: HashSet<ObjectID> =
+            self.coins.iter().map(|c| c.id()).collect();
+        self.locked.retain(|id| ids.contains(id));
+        Ok(())
+    }
+
+    fn reserve(&self) -> Option<&Coin> {
+        self.coins.last()
+    }
+
+    /// Locks the smallest free coin that covers the budget. The reserve is
+    /// only used when nothing else does.
+    pub fn pick(&mut self, gas_budget: u64) -> Result<ObjectRef> {
+        let coin = self
+            .coins
+            .iter()
+            .find(|c| c.balance >= gas_budget && !self.locked.contains(&c.id()))
+            .ok_or(anyhow!(
+                "no free gas coin of {} covers {}",
+                self.owner,
+                gas_budget
+            ))?;
+        self.locked.insert(coin.id());
+        Ok(coin.object_ref)
+    }
+
+    pub fn release(&mut self, object_ref: &ObjectRef) {
+        self.locked.remove(&object_ref.0);
+    }
+
+    /// True once per `maintain_interval`.
+    pub fn maintenance_due(&mut self) -> bool {
+        let now = misc::get_timestamp();
+        if now - self.last_maintain < self.maintain_interval {
+            return false;
+        }
+        self.last_maintain = now;
+        true
+    }
+
+    /// Builds a transaction paid by the reserve that merges dust into it and
+    /// splits off the coins the pool is missing. `None` if nothing to do.
+    pub fn maintenance(
+        &mut self,
+        gas_budget: u64,
+    ) -> Result<Option<(ObjectRef, ProgrammableTransaction)>> {
+        let reserve = match self.reserve() {
+            Some(reserve) if !self.locked.contains(&reserve.id()) => {
+                reserve.clone()
+            }
+            _ => return Ok(None),
+        };
+        let others = &self.coins[..self.coins.len() - 1];
+        let dust: Vec<&Coin> = others
+            .iter()
+            .filter(|c| c.balance < self.dust && !self.locked.contains(&c.id()))
+            .collect();
+        let pooled = others.iter().filter(|c| c.balance >= self.dust).count();
+        let missing = self.size.saturating_sub(pooled);
+        let needed = missing as u64 * self.coin_balance + gas_budget;
+        let missing = if reserve.balance > needed { missing } else { 0 };
+        if dust.is_empty() && missing == 0 {
+            return Ok(None);
+        }
+
+        let mut builder = ProgrammableTransactionBuilder::new();
+        if !dust.is_empty() {
+            let mut args = Vec::new();
+            for coin in dust.iter() {
+                args.push(
+                    builder
+                        .obj(ObjectArg::ImmOrOwnedObject(coin.object_ref))?,
+                );
+            }
+            builder.command(Command::MergeCoins(Argument::GasCoin, args));
+        }
+        if missing > 0 {
+            builder.pay_sui(
+                vec![self.owner; missing],
+                vec![self.coin_balance; missing],
+            )?;
+        }
+        self.locked.insert(reserve.id());
+        Ok(Some((reserve.object_ref, builder.finish())))
+    }
+}
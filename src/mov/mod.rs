@@ -1,288 +1,392 @@
 use anyhow::{anyhow, Ok, Result};
+use bip32::DerivationPath;
+use log::{debug, info};
+use move_core_types::identifier::Identifier;
 use move_core_types::language_storage::TypeTag;
 use move_core_types::u256::U256;
-use move_core_types::value::MoveValue;
-use serde_json::json;
-use shared_crypto::intent::Intent;
+use shared_crypto::intent::{Intent, IntentMessage};
 use std::f32::consts::E;
-use std::path::Path;
 use std::str::FromStr;
-use sui::client_commands::{SuiClientCommandResult, SuiClientCommands};
-use sui_json::MoveTypeLayout;
-use sui_json::SuiJsonValue;
+use std::time::Duration;
 use sui_json_rpc_types::SuiExecutionStatus;
 use sui_json_rpc_types::SuiTransactionBlockEffectsAPI;
-use sui_keys::keystore::AccountKeystore;
-use sui_keys::keystore::Keystore;
+use sui_json_rpc_types::{SuiObjectDataOptions, SuiTransactionBlockResponse};
+use sui_keys::key_derive;
 use sui_sdk::types::base_types::SuiAddress;
-use sui_sdk::wallet_context::WalletContext;
-use sui_types::base_types::ObjectID;
-use sui_types::crypto::SignatureScheme;
+use sui_types::base_types::{ObjectID, ObjectRef};
+use sui_types::crypto::{Signature, SignatureScheme, SuiKeyPair};
+use sui_types::gas::GasCostSummary;
+use sui_types::object::Owner;
+use sui_types::programmable_transaction_builder::ProgrammableTransactionBuilder;
 use sui_types::transaction::{
-    SenderSignedData, Transaction, TransactionData, TransactionDataAPI,
+    ObjectArg, ProgrammableTransaction, SenderSignedData, Transaction,
+    TransactionData, TransactionDataAPI, VerifiedTransaction,
 };
 
-use crate::misc;
+use crate::cosign::{self, Cosigner};
+use crate::signer::Signer;
 
+pub mod gas;
 pub mod multisig;
+pub mod rpc;
+pub mod treasury;
 pub mod utils;
+pub mod wallet;
 
-pub async fn init_wallet(
-    config_path: &String,
-    key: &String,
-    mnemonic: &String,
-) -> Result<WalletContext> {
-    let from_path_str = Path::new(config_path);
-    let mut wallet = WalletContext::new(from_path_str, None, None)
-        .await
-        .or(Err(anyhow!("Failed to get wallet context")))?;
-    let active_env = wallet
-        .config
-        .get_active_env()
-        .or(Err(anyhow!("Failed to get active env")));
-    println!("active env: {:?}", active_env);
-
-    match &mut wallet.config.keystore {
-        Keystore::File(file) => {
-            let mut ret: Result<SuiAddress, anyhow::Error> =
-                Err(anyhow!("import key/mnemonic failed"));
-            if key.len() > 0 {
-                ret = file.import_from_keystore(key);
-            } else if mnemonic.len() > 0 {
-                ret = file.import_from_mnemonic(
-                    mnemonic,
-                    SignatureScheme::ED25519,
-                    None,
-                );
-            }
-            match ret {
-                core::result::Result::Ok(_) => {
-                    println!("import mnemonic success")
-                }
-                Err(_) => return Err(anyhow!("import mnemonic failed")),
-            }
-        }
-        Keystore::InMem(_) => {}
-    }
+use wallet::Wallet;
 
-    for x in &wallet.get_addresses() {
-        println!("address :{}", x.to_string());
+/// Parses `ed25519`, `secp256k1` or `secp256r1`.
+pub fn parse_scheme(name: &str) -> Result<SignatureScheme> {
+    match name.to_lowercase().as_str() {
+        "ed25519" => Ok(SignatureScheme::ED25519),
+        "secp256k1" => Ok(SignatureScheme::Secp256k1),
+        "secp256r1" => Ok(SignatureScheme::Secp256r1),
+        _ => Err(anyhow!("unknown signature scheme {}", name)),
     }
+}
+
+/// Parses a bip32 path and checks it suits the scheme, e.g.
+/// m/44'/784'/0'/0'/0' for ed25519 or m/54'/784'/0'/0/0 for secp256k1.
+pub fn parse_derivation_path(
+    path: &str,
+    scheme: SignatureScheme,
+) -> Result<DerivationPath> {
+    let path = DerivationPath::from_str(path)
+        .map_err(|e| anyhow!("derivation path {}: {}", path, e))?;
+    key_derive::validate_path(&scheme, Some(path))
+        .map_err(|e| anyhow!("derivation path: {}", e))
+}
 
-    let active_address = wallet
-        .active_address()
-        .or(Err(anyhow!("Failed to get active address")))?;
-    println!("active address: {:?}", active_address);
+/// Connects to `rpc` and logs the balance of `address`. Keys are not
+/// involved; the signer holds them.
+pub async fn init_wallet(
+    rpc: &str,
+    chain_id: Option<String>,
+    address: &SuiAddress,
+) -> Result<Wallet> {
+    let wallet = Wallet::new(rpc, chain_id).await?;
+    info!("active address: {:?}", address);
 
-    let total_balance = get_total_gas_balance(&wallet, &active_address).await?;
-    println!("total balances: {}", total_balance);
+    let total_balance = get_total_gas_balance(&wallet, address).await?;
+    info!("total balances: {}", total_balance);
 
     Ok(wallet)
 }
 
-pub async fn get_balance(wallet: &mut WalletContext) -> Result<u64> {
-    let active_address = wallet
-        .active_address()
-        .or(Err(anyhow!("Failed to get active address")))?;
-    let balances = wallet.gas_objects(active_address).await?;
-    let mut total_balance = 0u64;
-    for gas in balances {
-        let v1 = gas.1;
-        println!(" {} = {}", v1.object_id, gas.0);
-        total_balance += gas.0;
-    }
-
-    Ok(total_balance)
+/// Sum of `coins`, or only of the `gas` coin if given.
+pub fn sum_gas(coins: &[(u64, ObjectID)], gas: Option<ObjectID>) -> u64 {
+    coins
+        .iter()
+        .filter(|(_, id)| gas.is_none() || gas == Some(*id))
+        .map(|(balance, _)| balance)
+        .sum()
 }
 
+/// Balance of the fixed gas coin of a multisig sender.
 pub async fn get_multi_balance(
-    wallet: &mut WalletContext,
+    wallet: &Wallet,
     multi_address: &String,
     gas_id: &String,
 ) -> Result<u64> {
     let active_address: SuiAddress = SuiAddress::from_str(multi_address)?;
     let gas_id = ObjectID::from_hex_literal(gas_id)?;
-    let balances = wallet.gas_objects(active_address).await?;
-    let mut total_balance = 0u64;
-    for gas in balances {
-        let v1 = gas.1;
-        println!(" {} = {}", v1.object_id, gas.0);
-        if gas_id == v1.object_id {
-            total_balance += gas.0;
-        }
-    }
-
-    Ok(total_balance)
+    let coins = get_gas_coins(wallet, &active_address).await?;
+    Ok(sum_gas(&coins, Some(gas_id)))
 }
 
+/// Balance of every sui coin of `address`.
 pub async fn get_total_gas_balance(
-    wallet: &WalletContext,
+    wallet: &Wallet,
     address: &SuiAddress,
 ) -> Result<u64> {
+    let coins = get_gas_coins(wallet, address).await?;
+    Ok(sum_gas(&coins, None))
+}
+
+async fn get_gas_coins(
+    wallet: &Wallet,
+    address: &SuiAddress,
+) -> Result<Vec<(u64, ObjectID)>> {
     let balances = wallet.gas_objects(*address).await?;
-    let mut total_balance = 0u64;
+    let mut coins = Vec::new();
     for gas in balances {
         let v1 = gas.1;
-        println!(" {} = {}", v1.object_id, gas.0);
-        total_balance += gas.0;
+        debug!("coin {} = {}", v1.coin_object_id, gas.0);
+        coins.push((gas.0, v1.coin_object_id));
     }
+    Ok(coins)
+}
+
+/// One `update_token_price_batch` call of a programmable transaction.
+#[derive(Debug, Clone)]
+pub struct UpdateCall {
+    pub package: ObjectID,
+    pub module: String,
+    pub function: String,
+    pub oracle_cap: ObjectID,
+    pub price_oracle: ObjectID,
+}
 
-    Ok(total_balance)
+/// Resolves an object to a transaction input: owned objects by reference,
+/// shared objects by their initial shared version.
+pub async fn get_object_arg(
+    wallet: &Wallet,
+    id: ObjectID,
+    mutable: bool,
+) -> Result<ObjectArg> {
+    let client = wallet.get_client().await?;
+    let response = client
+        .read_api()
+        .get_object_with_options(id, SuiObjectDataOptions::new().with_owner())
+        .await?;
+    let data = response.data.ok_or(anyhow!("object {} not found", id))?;
+    match data.owner {
+        Some(Owner::Shared {
+            initial_shared_version,
+        }) => Ok(ObjectArg::SharedObject {
+            id,
+            initial_shared_version,
+            mutable,
+        }),
+        Some(_) => Ok(ObjectArg::ImmOrOwnedObject(data.object_ref())),
+        None => Err(anyhow!("object {} has no owner", id)),
+    }
 }
 
-pub fn pack_params(
-    oracle_cap: &String,
-    price_oracle: &String,
-    pool_ids: &Vec<u8>,
-    token_prices: &Vec<u64>,
-) -> Result<Vec<SuiJsonValue>> {
-    let oracle_cap = ObjectID::from_hex_literal(oracle_cap)?;
-    let price_oracle = ObjectID::from_hex_literal(price_oracle)?;
-
-    let id_len = pool_ids.len();
-    let price_len = token_prices.len();
-    if id_len != price_len {
+/// Appends one batch update to the transaction being built. Several calls,
+/// e.g. to different oracle objects, can share one transaction.
+pub async fn add_update_call(
+    wallet: &Wallet,
+    builder: &mut ProgrammableTransactionBuilder,
+    call: &UpdateCall,
+    coin_idxs: &Vec<u8>,
+    prices: &Vec<u128>,
+    ts: u64,
+) -> Result<()> {
+    if coin_idxs.len() != prices.len() {
         return Err(anyhow!("prices error"));
     }
 
-    let mut idxs = Vec::new();
-    for i in 0..id_len {
-        idxs.push(MoveValue::U8(pool_ids[i]));
+    let oracle_cap = get_object_arg(wallet, call.oracle_cap, false).await?;
+    let price_oracle = get_object_arg(wallet, call.price_oracle, true).await?;
+    let prices: Vec<U256> = prices.iter().map(|p| U256::from(*p)).collect();
+    let tss = vec![ts; coin_idxs.len()];
+
+    let args = vec![
+        builder.obj(oracle_cap)?,
+        builder.obj(price_oracle)?,
+        builder.pure(coin_idxs)?,
+        builder.pure(prices)?,
+        builder.pure(tss)?,
+    ];
+    builder.programmable_move_call(
+        call.package,
+        Identifier::new(call.module.as_str())?,
+        Identifier::new(call.function.as_str())?,
+        vec![],
+        args,
+    );
+    Ok(())
+}
+
+/// Picks `gas_id` if given, otherwise the richest coin of `owner` that
+/// covers the budget.
+pub async fn select_gas(
+    wallet: &Wallet,
+    owner: SuiAddress,
+    gas_budget: u64,
+    gas_id: Option<ObjectID>,
+) -> Result<ObjectRef> {
+    let coins = wallet.gas_objects(owner).await?;
+    let coin = match gas_id {
+        Some(id) => coins.iter().find(|(_, c)| c.coin_object_id == id),
+        None => coins
+            .iter()
+            .filter(|(balance, _)| *balance >= gas_budget)
+            .max_by_key(|(balance, _)| *balance),
+    };
+    match coin {
+        Some((_, c)) => Ok(c.object_ref()),
+        None => Err(anyhow!("no gas coin of {} covers {}", owner, gas_budget)),
     }
-    let json_idxs = json!(idxs);
+}
 
-    let mut prices = Vec::new();
-    for i in 0..price_len {
-        prices.push(MoveValue::U256(token_prices[i].into()));
+pub async fn get_gas_price(
+    wallet: &Wallet,
+    gas_price: Option<u64>,
+) -> Result<u64> {
+    match gas_price {
+        Some(price) => Ok(price),
+        None => wallet.get_reference_gas_price().await,
     }
-    let prices_val = MoveValue::Vector(prices);
-    let prices_bytes = prices_val.simple_serialize().unwrap();
-
-    let ts = misc::get_timestamp();
-    let tss = vec![MoveValue::U64(ts); id_len];
-    let tss_val = MoveValue::Vector(tss);
-    let tss_bytes = tss_val.simple_serialize().unwrap();
-
-    Ok(vec![
-        SuiJsonValue::from_object_id(oracle_cap),
-        SuiJsonValue::from_object_id(price_oracle),
-        SuiJsonValue::new(json_idxs)?,
-        SuiJsonValue::from_bcs_bytes(
-            Some(&MoveTypeLayout::Vector(Box::new(MoveTypeLayout::U256))),
-            &prices_bytes,
-        )?,
-        SuiJsonValue::from_bcs_bytes(
-            Some(&MoveTypeLayout::Vector(Box::new(MoveTypeLayout::U64))),
-            &tss_bytes,
-        )?,
-    ])
 }
 
-pub async fn call(
-    wallet: &mut WalletContext,
-    package_id: &String,
-    module: &String,
-    function: &String,
+pub fn build_transaction(
+    sender: SuiAddress,
+    pt: ProgrammableTransaction,
+    gas: ObjectRef,
     gas_budget: u64,
-    params: Vec<SuiJsonValue>,
-) -> Result<SuiClientCommandResult> {
-    let package_id = ObjectID::from_hex_literal(package_id)?;
-
-    SuiClientCommands::Call {
-        package: package_id,
-        module: module.into(),
-        function: function.into(),
-        type_args: vec![],
-        args: params,
-        gas: None,
-        gas_budget: gas_budget,
-        serialize_unsigned_transaction: false,
-        serialize_signed_transaction: false,
+    gas_price: u64,
+) -> TransactionData {
+    TransactionData::new_programmable(
+        sender,
+        vec![gas],
+        pt,
+        gas_budget,
+        gas_price,
+    )
+}
+
+/// A move abort, decoded from the execution error of a transaction.
+#[derive(Debug, Clone)]
+pub struct MoveAbort {
+    pub module: Option<String>,
+    pub function: Option<String>,
+    pub code: u64,
+}
+
+fn between<'a>(s: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let from = s.find(start)? + start.len();
+    let to = s[from..].find(end)? + from;
+    Some(&s[from..to])
+}
+
+/// Parses errors like `MoveAbort(MoveLocation { module: ModuleId { ..,
+/// name: Identifier("oracle") }, .., function_name: Some("update") }, 3)`.
+pub fn decode_abort(error: &str) -> Option<MoveAbort> {
+    let abort = &error[error.find("MoveAbort(")?..];
+    let tail = &abort[abort.rfind("}, ")? + 3..];
+    let digits: String =
+        tail.chars().take_while(|c| c.is_ascii_digit()).collect();
+
+    Some(MoveAbort {
+        module: between(abort, "name: Identifier(\"", "\"").map(String::from),
+        function: between(abort, "function_name: Some(\"", "\"")
+            .map(String::from),
+        code: digits.parse().ok()?,
+    })
+}
+
+/// Simulates the transaction and returns its gas cost. Fails with the
+/// execution error if it would abort.
+pub async fn dry_run(
+    wallet: &Wallet,
+    tx_data: TransactionData,
+) -> Result<GasCostSummary> {
+    let client = wallet.get_client().await?;
+    let response = client.read_api().dry_run_transaction_block(tx_data).await?;
+    match response.effects.status() {
+        SuiExecutionStatus::Success => {
+            Ok(response.effects.gas_cost_summary().clone())
+        }
+        SuiExecutionStatus::Failure { error } => {
+            Err(anyhow!("dry run failed: {}", error))
+        }
     }
-    .execute(wallet)
-    .await
 }
 
-pub async fn multi_call(
-    wallet: &mut WalletContext,
-    package_id: &String,
-    module: &String,
-    function: &String,
-    gas_id: &String,
-    gas_budget: u64,
-    params: Vec<SuiJsonValue>,
+pub async fn sign_transaction(
+    signer: &dyn Signer,
+    tx_data: TransactionData,
+) -> Result<VerifiedTransaction> {
+    let signature = signer.sign(&tx_data).await?;
+    let verified = Transaction::from_data(
+        tx_data,
+        Intent::sui_transaction(),
+        vec![signature],
+    )
+    .verify()?;
+    Ok(verified)
+}
+
+/// Signs with a key outside the wallet's keystore, e.g. a treasury key.
+pub fn sign_with_key(
+    tx_data: TransactionData,
+    keypair: &SuiKeyPair,
+) -> Result<VerifiedTransaction> {
+    let signature = partial_signature(&tx_data, keypair);
+    let verified = Transaction::from_data(
+        tx_data,
+        Intent::sui_transaction(),
+        vec![signature],
+    )
+    .verify()?;
+    Ok(verified)
+}
+
+/// A signature by one key, as a multisig member contributes it.
+pub fn partial_signature(
+    tx_data: &TransactionData,
+    keypair: &SuiKeyPair,
+) -> Signature {
+    Signature::new_secure(
+        &IntentMessage::new(Intent::sui_transaction(), tx_data),
+        keypair,
+    )
+}
+
+/// Signs with our member key and gathers partial signatures from the
+/// co-signers until their weights reach the threshold.
+pub async fn sign_multisig_transaction(
+    signer: &dyn Signer,
+    tx_data: TransactionData,
     pubkeys: &Vec<String>,
     weights: &Vec<u8>,
     threshold: u16,
-) -> Result<SuiClientCommandResult> {
-    let package = ObjectID::from_hex_literal(package_id)?;
-    let gas = ObjectID::from_hex_literal(gas_id)?;
-    let tx_data = construct_move_call_transaction(
-        wallet,
-        package,
-        &module,
-        &function,
-        Some(gas),
-        gas_budget,
-        params,
-        &pubkeys,
-        &weights,
-        threshold,
+    cosigners: &[Cosigner],
+    timeout: Duration,
+) -> Result<VerifiedTransaction> {
+    let signature = signer.sign(&tx_data).await?;
+    let members = cosign::members(pubkeys, weights)?;
+    let sigs = cosign::collect_signatures(
+        &tx_data, signature, &members, threshold, cosigners, timeout,
     )
     .await?;
-    let signature = wallet.config.keystore.sign_secure(
-        &wallet.config.active_address.unwrap(),
-        &tx_data,
-        Intent::sui_transaction(),
-    )?;
-    let sigs = vec![signature];
-    let gen_sig = match self::multisig::multisig_combine_partialsig(
+    let gen_sig = self::multisig::multisig_combine_partialsig(
         sigs, pubkeys, weights, threshold,
-    ) {
-        core::result::Result::Ok(sig) => sig,
-        Err(e) => return Err(e),
-    };
-    let gen_sigs = vec![gen_sig];
+    )?;
     let verified = Transaction::from_generic_sig_data(
         tx_data,
         Intent::sui_transaction(),
-        gen_sigs,
+        vec![gen_sig],
     )
     .verify()?;
+    Ok(verified)
+}
 
+/// Executes a signed transaction and fails unless its effects report
+/// success.
+pub async fn execute(
+    wallet: &Wallet,
+    verified: VerifiedTransaction,
+) -> Result<SuiTransactionBlockResponse> {
     let response = wallet.execute_transaction_may_fail(verified).await?;
-    Ok(SuiClientCommandResult::ExecuteSignedTx(response))
+    let effects = response
+        .effects
+        .as_ref()
+        .ok_or(anyhow!("tx {} has no effects", response.digest))?;
+    match effects.status() {
+        SuiExecutionStatus::Success => Ok(response),
+        SuiExecutionStatus::Failure { error } => {
+            Err(anyhow!("tx {} failed: {}", response.digest, error))
+        }
+    }
 }
 
-async fn construct_move_call_transaction(
-    wallet: &mut WalletContext,
-    package: ObjectID,
-    module: &str,
-    function: &str,
-    gas: Option<ObjectID>,
-    gas_budget: u64,
-    args: Vec<SuiJsonValue>,
-    pubkeys: &Vec<String>,
-    weights: &Vec<u8>,
-    threshold: u16,
-) -> Result<TransactionData, anyhow::Error> {
-    let multi_address = self::multisig::multisig_address(
-        pubkeys.clone(),
-        weights.clone(),
-        threshold,
-    )?;
-    let client = wallet.get_client().await?;
-    client
-        .transaction_builder()
-        .move_call(
-            multi_address,
-            package,
-            module,
-            function,
-            vec![],
-            args,
-            gas,
-            gas_budget,
-        )
-        .await
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_up_coin_counts_with_a_gas_pool() {
+        let gas = ObjectID::from_single_byte(1);
+        let mut coins = vec![(500, gas), (200, ObjectID::from_single_byte(2))];
+        assert_eq!(sum_gas(&coins, None), 700);
+        assert_eq!(sum_gas(&coins, Some(gas)), 500);
+
+        // A top up arrives as a new coin of the sender.
+        coins.push((1000, ObjectID::from_single_byte(3)));
+        assert_eq!(sum_gas(&coins, None), 1700);
+        assert_eq!(sum_gas(&coins, Some(gas)), 500);
+    }
 }
@@ -0,0 +1,214 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use sui_sdk::{SuiClient, SuiClientBuilder};
+
+use crate::configuration::RpcPoolConfig;
+use crate::prom;
+
+pub const DEFAULT_PROBE_INTERVAL: u64 = 30;
+pub const DEFAULT_PROBE_TIMEOUT: u64 = 5;
+pub const DEFAULT_MAX_LAG: u64 = 20;
+
+/// What the last probe of an endpoint found.
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    pub url: String,
+    pub chain_id: Option<String>,
+    pub checkpoint: u64,
+    pub latency: Option<Duration>,
+    /// Set by a failed probe or transaction, cleared by the next good probe.
+    pub failed: bool,
+}
+
+/// Sui rpc endpoints ranked by a background prober. Endpoints on another
+/// chain, failing, or more than `max_lag` checkpoints behind the best one
+/// are left out; the rest are ordered by latency.
+#[derive(Debug, Clone)]
+pub struct RpcPool {
+    chain_id: Option<String>,
+    interval: Duration,
+    timeout: Duration,
+    max_lag: u64,
+    health: Arc<Mutex<Vec<Health>>>,
+}
+
+impl RpcPool {
+    pub fn new(
+        urls: &[String],
+        chain_id: Option<String>,
+        cfg: Option<&RpcPoolConfig>,
+    ) -> RpcPool {
+        let health = urls
+            .iter()
+            .map(|url| Health {
+                url: url.clone(),
+                ..Default::default()
+            })
+            .collect();
+        RpcPool {
+            chain_id,
+            interval: Duration::from_secs(
+                cfg.and_then(|c| c.probe_interval)
+                    .unwrap_or(DEFAULT_PROBE_INTERVAL),
+            ),
+            timeout: Duration::from_secs(
+                cfg.and_then(|c| c.probe_timeout)
+                    .unwrap_or(DEFAULT_PROBE_TIMEOUT),
+            ),
+            max_lag: cfg.and_then(|c| c.max_lag).unwrap_or(DEFAULT_MAX_LAG),
+            health: Arc::new(Mutex::new(health)),
+        }
+    }
+
+    fn on_chain(&self, h: &Health) -> bool {
+        match &self.chain_id {
+            Some(id) => h.chain_id.as_ref() == Some(id),
+            None => true,
+        }
+    }
+
+    /// Endpoints on the chain, not failed and within `max_lag` of the
+    /// highest checkpoint, fastest first.
+    fn usable(&self, health: &[Health]) -> Vec<String> {
+        let top = health
+            .iter()
+            .filter(|h| !h.failed && self.on_chain(h))
+            .map(|h| h.checkpoint)
+            .max()
+            .unwrap_or(0);
+        let mut usable: Vec<&Health> = health
+            .iter()
+            .filter(|h| {
+                !h.failed
+                    && h.latency.is_some()
+                    && self.on_chain(h)
+                    && h.checkpoint + self.max_lag >= top
+            })
+            .collect();
+        usable.sort_by_key(|h| h.latency);
+        usable.iter().map(|h| h.url.clone()).collect()
+    }
+
+    /// Usable endpoints, fastest first. When none is usable all of them are
+    /// returned, failed ones last, so there is still something to try.
+    pub fn ranked(&self) -> Vec<String> {
+        let health = self.health.lock().unwrap();
+        let usable = self.usable(&health);
+        if !usable.is_empty() {
+            return usable;
+        }
+        let mut all: Vec<&Health> = health.iter().collect();
+        all.sort_by_key(|h| h.failed);
+        all.iter().map(|h| h.url.clone()).collect()
+    }
+
+    pub fn best(&self) -> Option<String> {
+        self.ranked().into_iter().next()
+    }
+
+    /// Takes the endpoint out until its next good probe.
+    pub fn mark_failed(&self, url: &str) {
+        let mut health = self.health.lock().unwrap();
+        if let Some(h) = health.iter_mut().find(|h| h.url == url) {
+            h.failed = true;
+            prom::set_rpc_usable(url, false);
+        }
+    }
+
+    async fn probe(
+        &self,
+        url: &str,
+        clients: &mut HashMap<String, SuiClient>,
+    ) -> Result<(String, u64, Duration)> {
+        let client = match clients.get(url) {
+            Some(client) => client.clone(),
+            None => {
+                let client = SuiClientBuilder::default()
+                    .request_timeout(self.timeout)
+                    .build(url);
+                let client = tokio::time::timeout(self.timeout, client)
+                    .await
+                    .map_err(|_| anyhow!("connect timed out"))??;
+                clients.insert(url.to_owned(), client.clone());
+                client
+            }
+        };
+
+        let start = Instant::now();
+        let checkpoint = client
+            .read_api()
+            .get_latest_checkpoint_sequence_number()
+            .await?;
+        let latency = start.elapsed();
+        let chain_id = client.read_api().get_chain_identifier().await?;
+        Ok((chain_id, checkpoint, latency))
+    }
+
+    /// Probes every endpoint once and records the results.
+    pub async fn probe_all(&self, clients: &mut HashMap<String, SuiClient>) {
+        let urls: Vec<String> = self
+            .health
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|h| h.url.clone())
+            .collect();
+        for url in urls.iter() {
+            let result = self.probe(url, clients).await;
+            let mut health = self.health.lock().unwrap();
+            let h = match health.iter_mut().find(|h| &h.url == url) {
+                Some(h) => h,
+                None => continue,
+            };
+            match result {
+                Ok((chain_id, checkpoint, latency)) => {
+                    if h.failed {
+                        info!("rpc {} is back", url);
+                    }
+                    h.chain_id = Some(chain_id);
+                    h.checkpoint = checkpoint;
+                    h.latency = Some(latency);
+                    h.failed = false;
+                    prom::set_rpc_probe(url, latency.as_secs_f64(), checkpoint);
+                }
+                Err(e) => {
+                    warn!("rpc {}: probe: {}", url, e);
+                    // A stale client may be the cause; rebuild it next time.
+                    clients.remove(url);
+                    h.failed = true;
+                }
+            }
+        }
+
+        let health = self.health.lock().unwrap();
+        let usable = self.usable(&health);
+        for h in health.iter().filter(|h| !h.failed) {
+            if !self.on_chain(h) {
+                warn!("rpc {} is on chain {:?}", h.url, h.chain_id);
+            } else if !usable.contains(&h.url) {
+                warn!("rpc {} lags at checkpoint {}", h.url, h.checkpoint);
+            }
+        }
+        for h in health.iter() {
+            prom::set_rpc_usable(&h.url, usable.contains(&h.url));
+        }
+    }
+
+    /// Probes once, then keeps probing in the background.
+    pub async fn start(&self) {
+        let mut clients = HashMap::new();
+        self.probe_all(&mut clients).await;
+        info!("rpcs ranked: {:?}", self.ranked());
+
+        let pool = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(pool.interval).await;
+                pool.probe_all(&mut clients).await;
+            }
+        });
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use sui_sdk::types::base_types::SuiAddress;
+use sui_types::crypto::{EncodeDecodeBase64, SuiKeyPair};
+use sui_types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+
+use super::wallet::Wallet;
+use super::{
+    build_transaction, execute, get_gas_price, select_gas, sign_with_key,
+};
+use crate::configuration::TopUpConfig;
+use crate::misc;
+use crate::request;
+
+const DAY_MS: u64 = 86400000;
+/// Mist, ample for a single pay_sui.
+const TRANSFER_GAS_BUDGET: u64 = 10000000;
+
+enum Source {
+    Key(SuiAddress, SuiKeyPair),
+    Http(String),
+}
+
+/// One top up sent to the feeder.
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub amount: u64,
+    pub digest: Option<String>,
+}
+
+/// Refills accounts from a treasury key or a faucet endpoint, at most
+/// `daily_cap` mist per 24 hours across all of them. The transfers of the
+/// last day are kept at `path` so the cap holds across restarts.
+pub struct Treasury {
+    source: Source,
+    threshold: u64,
+    amount: u64,
+    daily_cap: u64,
+    path: String,
+    transfers: VecDeque<(u64, u64)>,
+}
+
+/// `(ts, amount)` of the transfers saved at `path`.
+fn load_transfers(path: &str) -> VecDeque<(u64, u64)> {
+    match std::fs::read(path) {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(transfers) => transfers,
+            Err(e) => {
+                warn!("top up log {} unreadable: {}", path, e);
+                VecDeque::new()
+            }
+        },
+        Err(_) => VecDeque::new(),
+    }
+}
+
+impl Treasury {
+    pub fn new(
+        cfg: &TopUpConfig,
+        threshold: u64,
+        path: &str,
+    ) -> Result<Treasury> {
+        let source = match (&cfg.key_env, &cfg.url) {
+            (Some(key_env), None) => {
+                let key = std::env::var(key_env)
+                    .map_err(|_| anyhow!("{} not set", key_env))?;
+                let keypair = SuiKeyPair::decode_base64(&key)
+                    .map_err(|e| anyhow!("{}: {}", key_env, e))?;
+                Source::Key((&keypair.public()).into(), keypair)
+            }
+            (None, Some(url)) => Source::Http(url.clone()),
+            _ => return Err(anyhow!("top_up needs one of key_env and url")),
+        };
+        Ok(Treasury {
+            source,
+            threshold: cfg.threshold.unwrap_or(threshold),
+            amount: cfg.amount,
+            daily_cap: cfg.daily_cap,
+            path: path.to_owned(),
+            transfers: load_transfers(path),
+        })
+    }
+
+    /// Writes through a temporary file like the price history.
+    fn save(&self) -> Result<()> {
+        let tmp = format!("{}.tmp", self.path);
+        std::fs::write(&tmp, serde_json::to_vec(&self.transfers)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Mist still allowed in the current 24 hours.
+    fn allowance(&mut self, now: u64) -> u64 {
+        while let Some((ts, _)) = self.transfers.front() {
+            if now.saturating_sub(*ts) < DAY_MS {
+                break;
+            }
+            self.transfers.pop_front();
+        }
+        let sent: u64 = self.transfers.iter().map(|(_, a)| a).sum();
+        self.daily_cap.saturating_sub(sent)
+    }
+
+    /// Transfers to `recipient` if `balance` is below the threshold and the
+    /// daily cap allows it. The last top up of a day may be partial.
+    pub async fn top_up(
+        &mut self,
+        wallet: &Wallet,
+        recipient: SuiAddress,
+        balance: u64,
+    ) -> Result<Option<Transfer>> {
+        if balance >= self.threshold {
+            return Ok(None);
+        }
+        let now = misc::get_timestamp();
+        let amount = self.amount.min(self.allowance(now));
+        if amount == 0 {
+            return Err(anyhow!(
+                "{} below {} but the daily cap {} is spent",
+                balance,
+                self.threshold,
+                self.daily_cap
+            ));
+        }
+
+        let digest = match &self.source {
+            Source::Key(address, keypair) => Some(
+                transfer(wallet, *address, keypair, recipient, amount).await?,
+            ),
+            Source::Http(url) => request_faucet(url, recipient, amount).await?,
+        };
+        self.transfers.push_back((now, amount));
+        if let Err(e) = self.save() {
+            warn!("save top up log {} failed: {}", self.path, e);
+        }
+        Ok(Some(Transfer { amount, digest }))
+    }
+}
+
+async fn transfer(
+    wallet: &Wallet,
+    sender: SuiAddress,
+    keypair: &SuiKeyPair,
+    recipient: SuiAddress,
+    amount: u64,
+) -> Result<String> {
+    let gas_budget = TRANSFER_GAS_BUDGET;
+    let gas = select_gas(wallet, sender, amount + gas_budget, None).await?;
+    let gas_price = get_gas_price(wallet, None).await?;
+
+    let mut builder = ProgrammableTransactionBuilder::new();
+    builder.pay_sui(vec![recipient], vec![amount])?;
+    let tx_data =
+        build_transaction(sender, builder.finish(), gas, gas_budget, gas_price);
+    let verified = sign_with_key(tx_data, keypair)?;
+    let response = execute(wallet, verified).await?;
+    Ok(response.digest.to_string())
+}
+
+/// Posts `{recipient, amount}` and takes the digest from the response, if
+/// the endpoint returns one.
+async fn request_faucet(
+    url: &String,
+    recipient: SuiAddress,
+    amount: u64,
+) -> Result<Option<String>> {
+    let body = json!({
+        "recipient": recipient.to_string(),
+        "amount": amount,
+    });
+    let response: Value = request::post(url, &body).await?;
+    if let Some(error) = response.get("error").filter(|e| !e.is_null()) {
+        return Err(anyhow!("faucet: {}", error));
+    }
+    Ok(response
+        .get("digest")
+        .and_then(|d| d.as_str())
+        .map(String::from))
+}
@@ -2,21 +2,29 @@ use base64_light::*;
 use bip39::{Language, Mnemonic};
 use std::error::Error;
 
-pub fn is_valid_base64_key(key: &str) -> bool {
+/// Flag bytes of the supported schemes: ed25519, secp256k1 and secp256r1.
+pub const KEY_FLAGS: [u8; 3] = [0x00, 0x01, 0x02];
+
+/// The scheme flag of a base64 `flag || private key`, if it is one.
+pub fn key_flag(key: &str) -> Option<u8> {
     if key.len() == 0 {
-        return false;
+        return None;
     }
 
     let decoded = base64_decode(key);
     if decoded.len() != 33 {
-        return false;
+        return None;
     }
 
-    if decoded[0] != 0x00 {
-        return false;
+    if !KEY_FLAGS.contains(&decoded[0]) {
+        return None;
     }
 
-    true
+    Some(decoded[0])
+}
+
+pub fn is_valid_base64_key(key: &str) -> bool {
+    key_flag(key).is_some()
 }
 
 pub fn is_valid_mnemonic(mne: &str) -> bool {
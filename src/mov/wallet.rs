@@ -0,0 +1,112 @@
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+use sui_json_rpc_types::{
+    Coin, SuiTransactionBlockResponse, SuiTransactionBlockResponseOptions,
+};
+use sui_sdk::types::base_types::SuiAddress;
+use sui_sdk::{SuiClient, SuiClientBuilder};
+use sui_types::quorum_driver_types::ExecuteTransactionRequestType;
+use sui_types::transaction::VerifiedTransaction;
+
+const REQUEST_TIMEOUT: u64 = 60;
+
+/// A sui client built from tiny.yaml alone. Keys are held by the signer, so
+/// nothing is read from or written to a client.yaml or keystore file.
+pub struct Wallet {
+    rpc: String,
+    chain_id: Option<String>,
+    client: SuiClient,
+}
+
+/// Connects and, if given, checks the node is on `chain_id`.
+async fn connect(rpc: &str, chain_id: &Option<String>) -> Result<SuiClient> {
+    let client = SuiClientBuilder::default()
+        .request_timeout(Duration::from_secs(REQUEST_TIMEOUT))
+        .build(rpc)
+        .await
+        .map_err(|e| anyhow!("{}: {}", rpc, e))?;
+    if let Some(chain_id) = chain_id {
+        let actual = client.read_api().get_chain_identifier().await?;
+        if &actual != chain_id {
+            return Err(anyhow!(
+                "{} is on chain {}, not {}",
+                rpc,
+                actual,
+                chain_id
+            ));
+        }
+    }
+    Ok(client)
+}
+
+impl Wallet {
+    pub async fn new(rpc: &str, chain_id: Option<String>) -> Result<Wallet> {
+        let client = connect(rpc, &chain_id).await?;
+        Ok(Wallet {
+            rpc: rpc.to_owned(),
+            chain_id,
+            client,
+        })
+    }
+
+    pub fn rpc(&self) -> &str {
+        &self.rpc
+    }
+
+    pub async fn get_client(&self) -> Result<SuiClient> {
+        Ok(self.client.clone())
+    }
+
+    /// Switches to another rpc. The current one is kept if that fails.
+    pub async fn set_client(&mut self, rpc: &str) -> Result<()> {
+        self.client = connect(rpc, &self.chain_id).await?;
+        self.rpc = rpc.to_owned();
+        Ok(())
+    }
+
+    /// All sui coins of `owner` with their balances.
+    pub async fn gas_objects(
+        &self,
+        owner: SuiAddress,
+    ) -> Result<Vec<(u64, Coin)>> {
+        let mut coins = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = self
+                .client
+                .coin_read_api()
+                .get_coins(owner, None, cursor, None)
+                .await?;
+            coins.extend(page.data.into_iter().map(|c| (c.balance, c)));
+            if !page.has_next_page {
+                break;
+            }
+            cursor = page.next_cursor;
+        }
+        Ok(coins)
+    }
+
+    pub async fn get_reference_gas_price(&self) -> Result<u64> {
+        Ok(self.client.read_api().get_reference_gas_price().await?)
+    }
+
+    /// Executes and waits for local execution. Failed effects are returned,
+    /// not raised.
+    pub async fn execute_transaction_may_fail(
+        &self,
+        tx: VerifiedTransaction,
+    ) -> Result<SuiTransactionBlockResponse> {
+        let response = self
+            .client
+            .quorum_driver_api()
+            .execute_transaction_block(
+                tx,
+                SuiTransactionBlockResponseOptions::new()
+                    .with_effects()
+                    .with_events(),
+                Some(ExecuteTransactionRequestType::WaitForLocalExecution),
+            )
+            .await?;
+        Ok(response)
+    }
+}
@@ -0,0 +1,68 @@
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use crate::decimal::{Decimal, BPS};
+
+lazy_static! {
+    static ref DEPEGGED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+#[derive(Debug, Clone)]
+pub enum Reference {
+    /// A fixed usd value, e.g. 1.0 for a stablecoin.
+    Value(Decimal),
+    /// The live price of another coin, by its index in `coins`.
+    Coin(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Peg {
+    pub reference: Reference,
+    pub band_bps: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub price: Decimal,
+    pub depegged: bool,
+    pub changed: bool,
+    pub diff_bps: Decimal,
+}
+
+pub fn to_bps(fraction: f64) -> u32 {
+    (fraction * 10000.0).round() as u32
+}
+
+/// Publishes `reference` while `market` stays within the band around it and
+/// falls back to `market` once it leaves. `changed` is set only on the round
+/// where the coin enters or leaves the depegged state.
+pub fn resolve(
+    symbol: &str,
+    market: Decimal,
+    reference: Decimal,
+    band_bps: u32,
+) -> Outcome {
+    let diff_bps = match (market - reference).abs().checked_mul(BPS) {
+        Some(d) => d / reference,
+        None => Decimal::MAX,
+    };
+    let depegged = diff_bps > Decimal::from(band_bps);
+
+    let changed = {
+        let mut state = DEPEGGED.lock().unwrap();
+        if depegged {
+            state.insert(symbol.to_owned())
+        } else {
+            state.remove(symbol)
+        }
+    };
+
+    let price = if depegged { market } else { reference };
+    Outcome {
+        price,
+        depegged,
+        changed,
+        diff_bps,
+    }
+}
@@ -1,13 +1,61 @@
 use lazy_static::lazy_static;
-use prometheus::{labels, register_gauge, register_int_gauge, Gauge, IntGauge};
+use prometheus::{
+    labels, register_gauge, register_gauge_vec, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Gauge, GaugeVec, IntCounterVec,
+    IntGauge, IntGaugeVec,
+};
 
 use crate::misc;
 
+const RPC_PREFIX: &str = "sui_rpc_";
+
 lazy_static! {
     static ref BALANCE_GAUGE: Gauge =
         register_gauge!("balance_status", "help").unwrap();
     static ref TS_GAUGE: IntGauge =
         register_int_gauge!("push_timestamp", "help").unwrap();
+    static ref RPC_LATENCY_GAUGE: GaugeVec = register_gauge_vec!(
+        "sui_rpc_latency_seconds",
+        "latency of the last rpc probe",
+        &["rpc"]
+    )
+    .unwrap();
+    static ref RPC_CHECKPOINT_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        "sui_rpc_checkpoint",
+        "latest checkpoint seen by the rpc",
+        &["rpc"]
+    )
+    .unwrap();
+    static ref RPC_USABLE_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        "sui_rpc_usable",
+        "1 if the rpc is healthy, synced and on the chain",
+        &["rpc"]
+    )
+    .unwrap();
+    static ref RPC_TX_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "sui_rpc_transactions",
+        "transactions sent through each rpc",
+        &["sink", "rpc"]
+    )
+    .unwrap();
+}
+
+pub fn set_rpc_probe(rpc: &str, latency: f64, checkpoint: u64) {
+    RPC_LATENCY_GAUGE.with_label_values(&[rpc]).set(latency);
+    RPC_CHECKPOINT_GAUGE
+        .with_label_values(&[rpc])
+        .set(checkpoint as i64);
+}
+
+pub fn set_rpc_usable(rpc: &str, usable: bool) {
+    RPC_USABLE_GAUGE
+        .with_label_values(&[rpc])
+        .set(usable as i64);
+}
+
+/// Counts a transaction `sink` sent through `rpc`.
+pub fn count_rpc_transaction(sink: &str, rpc: &str) {
+    RPC_TX_COUNTER.with_label_values(&[sink, rpc]).inc();
 }
 
 #[derive(Debug, Clone)]
@@ -71,4 +119,32 @@ impl Prom {
             Err(e) => eprintln!("push failed: {}", e),
         }
     }
+
+    /// Pushes the rpc pool metrics on their own, grouped without an
+    /// account. Nothing is pushed before a pool has probed.
+    pub fn push_rpc(&self, ip: &String, env: &String) {
+        let metric_families: Vec<_> = prometheus::gather()
+            .into_iter()
+            .filter(|m| m.get_name().starts_with(RPC_PREFIX))
+            .collect();
+        if metric_families.is_empty() {
+            return;
+        }
+
+        if let Err(e) = prometheus::push_metrics(
+            &self.job,
+            labels! {
+                "ip".to_owned() => ip.to_owned(),
+                "env".to_owned() => env.to_owned(),
+            },
+            &self.url,
+            metric_families,
+            Some(prometheus::BasicAuthentication {
+                username: self.username.to_owned(),
+                password: self.password.to_owned(),
+            }),
+        ) {
+            eprintln!("push rpc metrics failed: {}", e);
+        }
+    }
 }
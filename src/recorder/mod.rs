@@ -0,0 +1,190 @@
+use anyhow::Result;
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use crate::decimal::Decimal;
+use crate::storage::Storage;
+
+const FILE_PREFIX: &str = "records-";
+const FILE_SUFFIX: &str = ".jsonl.gz";
+
+/// Everything that went into and came out of one round.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundRecord {
+    pub round: u64,
+    pub ts: u64,
+    pub coins: Vec<String>,
+    /// Parsed quotes per source, in the quote currency of the source.
+    pub storage: Storage,
+    /// Aggregated usd prices, before smoothing and bounds.
+    pub aggregated: Vec<Decimal>,
+    /// Inputs each derived coin was evaluated with, by symbol.
+    #[serde(default)]
+    pub derived: BTreeMap<String, BTreeMap<String, Decimal>>,
+    pub coin_idxs: Vec<u8>,
+    pub price_vals: Vec<u128>,
+}
+
+/// The outcome of publishing a round to one sink. `digest` is none if every
+/// attempt failed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxRecord {
+    pub round: u64,
+    pub ts: u64,
+    pub sink: String,
+    pub digest: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Entry {
+    Round(RoundRecord),
+    Tx(TxRecord),
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    #[serde(flatten)]
+    entry: &'a Entry,
+    /// Sha256 of the previous line, chaining the records of a run.
+    prev: &'a str,
+}
+
+/// Appends entries to gzip compressed jsonl files under `dir`. Each line is
+/// its own gzip member, so a crash never leaves a file unreadable.
+#[derive(Debug)]
+pub struct Recorder {
+    dir: PathBuf,
+    rotate_rounds: u64,
+    keep_files: Option<usize>,
+    current: Option<PathBuf>,
+    rounds: u64,
+    prev: String,
+}
+
+impl Recorder {
+    pub fn new(
+        dir: &str,
+        rotate_rounds: u64,
+        keep_files: Option<usize>,
+    ) -> Result<Recorder> {
+        std::fs::create_dir_all(dir)?;
+        let dir = PathBuf::from(dir);
+        let prev = match Self::files(&dir)?.last() {
+            Some(newest) => last_hash(newest),
+            None => String::new(),
+        };
+        Ok(Recorder {
+            dir,
+            rotate_rounds: rotate_rounds.max(1),
+            keep_files,
+            current: None,
+            rounds: 0,
+            prev,
+        })
+    }
+
+    /// Record files under `dir`, oldest first.
+    fn files(dir: &PathBuf) -> Result<Vec<PathBuf>> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|n| n.starts_with(FILE_PREFIX))
+                    .unwrap_or(false)
+            })
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    fn rotate(&mut self, round: u64) -> Result<PathBuf> {
+        let name = format!("{}{:020}{}", FILE_PREFIX, round, FILE_SUFFIX);
+        let path = self.dir.join(name);
+        self.current = Some(path.clone());
+        self.rounds = 0;
+        self.prune()?;
+        Ok(path)
+    }
+
+    /// Makes room for the file about to be created.
+    fn prune(&self) -> Result<()> {
+        let keep = match self.keep_files {
+            Some(keep) => keep.max(1),
+            None => return Ok(()),
+        };
+        let mut files = Self::files(&self.dir)?;
+        while files.len() >= keep {
+            let oldest = files.remove(0);
+            if let Err(e) = std::fs::remove_file(&oldest) {
+                warn!("remove {} failed: {}", oldest.display(), e);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record(&mut self, entry: &Entry) -> Result<()> {
+        let path = match (&entry, &self.current) {
+            (Entry::Round(r), _) if self.rounds >= self.rotate_rounds => {
+                self.rotate(r.round)?
+            }
+            (Entry::Round(r), None) => self.rotate(r.round)?,
+            (_, Some(path)) => path.clone(),
+            (Entry::Tx(t), None) => self.rotate(t.round)?,
+        };
+        if let Entry::Round(_) = entry {
+            self.rounds += 1;
+        }
+
+        let line = serde_json::to_string(&Line {
+            entry,
+            prev: &self.prev,
+        })?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(line.as_bytes())?;
+        encoder.write_all(b"\n")?;
+        let bytes = encoder.finish()?;
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        file.write_all(&bytes)?;
+
+        self.prev = hex::encode(Sha256::digest(line.as_bytes()));
+        Ok(())
+    }
+}
+
+/// Hash of the last readable line of `path`, so a restart continues the
+/// chain of the previous run. A torn last line is skipped.
+fn last_hash(path: &PathBuf) -> String {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("open {} failed: {}", path.display(), e);
+            return String::new();
+        }
+    };
+    let mut last = None;
+    for line in BufReader::new(MultiGzDecoder::new(file)).lines() {
+        match line {
+            Ok(line) if !line.is_empty() => last = Some(line),
+            Ok(_) => {}
+            Err(e) => {
+                warn!("read {} failed: {}", path.display(), e);
+                break;
+            }
+        }
+    }
+    match last {
+        Some(line) => hex::encode(Sha256::digest(line.as_bytes())),
+        None => String::new(),
+    }
+}
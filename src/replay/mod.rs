@@ -0,0 +1,236 @@
+use anyhow::{anyhow, Result};
+use flate2::read::MultiGzDecoder;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::decimal::{Decimal, BPS};
+use crate::exchange;
+use crate::storage::Storage;
+
+/// One round of raw source quotes as fetched from the exchanges.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub round: u64,
+    pub ts: u64,
+    pub coins: Vec<String>,
+    pub storage: Storage,
+}
+
+/// Reads plain or gzip compressed jsonl. Recorder lines other than rounds
+/// are skipped.
+fn read_file(path: &Path, snapshots: &mut Vec<Snapshot>) -> Result<()> {
+    let file = std::fs::File::open(path)?;
+    let reader: Box<dyn BufRead> =
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+            _ => Box::new(BufReader::new(file)),
+        };
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: Value = serde_json::from_str(&line)
+            .map_err(|e| anyhow!("{}:{}: {}", path.display(), n + 1, e))?;
+        match value.get("kind").and_then(|k| k.as_str()) {
+            None | Some("round") => {}
+            Some(_) => continue,
+        }
+        let snapshot: Snapshot = serde_json::from_value(value)
+            .map_err(|e| anyhow!("{}:{}: {}", path.display(), n + 1, e))?;
+        snapshots.push(snapshot);
+    }
+    Ok(())
+}
+
+/// Reads snapshots from a jsonl or recorder file, or from every file of a
+/// directory in name order. Snapshots are returned sorted by timestamp.
+pub fn read_snapshots(path: &str) -> Result<Vec<Snapshot>> {
+    let path = Path::new(path);
+    let mut snapshots = Vec::new();
+    if path.is_dir() {
+        let mut files: Vec<_> = std::fs::read_dir(path)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_file())
+            .collect();
+        files.sort();
+        for file in files.iter() {
+            read_file(file, &mut snapshots)?;
+        }
+    } else {
+        read_file(path, &mut snapshots)?;
+    }
+    snapshots.sort_by_key(|s| s.ts);
+    Ok(snapshots)
+}
+
+/// Re-indexes the quotes of `snapshot` to `coins` by symbol, so decimals,
+/// algorithms and pegs of the current config apply to the right coin. Coins
+/// the snapshot lacks are left without quotes and returned.
+pub fn align(snapshot: &Snapshot, coins: &[String]) -> (Snapshot, Vec<String>) {
+    let tickers = snapshot.storage.tickers.len();
+    let mut storage = Storage::new(tickers, coins.len());
+    let mut missing = Vec::new();
+    for (i, coin) in coins.iter().enumerate() {
+        let from = match snapshot.coins.iter().position(|c| c == coin) {
+            Some(from) => from,
+            None => {
+                missing.push(coin.clone());
+                continue;
+            }
+        };
+        let pairs = snapshot.storage.tickers.iter().zip(&mut storage.tickers);
+        for (src, dst) in pairs {
+            dst.prices[i] = src.prices.get(from).copied().unwrap_or_default();
+            dst.volumes[i] = src.volumes.get(from).copied().unwrap_or_default();
+            dst.timestamps[i] = src.timestamps.get(from).copied().unwrap_or(0);
+        }
+    }
+    let aligned = Snapshot {
+        round: snapshot.round,
+        ts: snapshot.ts,
+        coins: coins.to_vec(),
+        storage,
+    };
+    (aligned, missing)
+}
+
+/// Median of the live source quotes of coin `idx`, in usd.
+pub fn source_median(
+    storage: &Storage,
+    idx: usize,
+    usdt_price: Decimal,
+) -> Option<Decimal> {
+    let mut quotes = Vec::new();
+    for (i, ticker) in storage.tickers.iter().enumerate() {
+        let price = match ticker.prices.get(idx) {
+            Some(p) if !p.is_zero() => *p,
+            _ => continue,
+        };
+        match exchange::QUOTES.get(i) {
+            Some(&"USD") => quotes.push(price),
+            Some(_) => quotes.push(price * usdt_price),
+            None => {}
+        }
+    }
+    if quotes.is_empty() {
+        return None;
+    }
+
+    quotes.sort();
+    let len = quotes.len();
+    if len % 2 == 0 {
+        Some((quotes[len / 2 - 1] + quotes[len / 2]) / Decimal::TWO)
+    } else {
+        Some(quotes[len / 2])
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CoinReport {
+    pub rounds: u64,
+    pub published: u64,
+    pub skipped: u64,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+    pub last: Option<Decimal>,
+    deviation_sum: Decimal,
+    deviation_count: u64,
+    pub max_deviation_bps: Decimal,
+}
+
+impl CoinReport {
+    pub fn mean_deviation_bps(&self) -> Decimal {
+        if self.deviation_count == 0 {
+            return Decimal::ZERO;
+        }
+        self.deviation_sum / Decimal::from(self.deviation_count)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub rounds: u64,
+    pub transactions: u64,
+    /// Sum of the gas budgets, the most the transactions could have cost.
+    pub gas_budget: u64,
+    pub coins: BTreeMap<String, CoinReport>,
+}
+
+impl Report {
+    pub fn skip(&mut self, symbol: &str) {
+        let coin = self.coins.entry(symbol.to_owned()).or_default();
+        coin.rounds += 1;
+        coin.skipped += 1;
+    }
+
+    /// Records a published price and its distance from the source median.
+    pub fn publish(
+        &mut self,
+        symbol: &str,
+        price: Decimal,
+        median: Option<Decimal>,
+    ) {
+        let coin = self.coins.entry(symbol.to_owned()).or_default();
+        coin.rounds += 1;
+        coin.published += 1;
+        coin.min = Some(coin.min.map_or(price, |m| m.min(price)));
+        coin.max = Some(coin.max.map_or(price, |m| m.max(price)));
+        coin.last = Some(price);
+
+        if let Some(median) = median.filter(|m| !m.is_zero()) {
+            let deviation = (price - median).abs() * BPS / median;
+            coin.deviation_sum += deviation;
+            coin.deviation_count += 1;
+            coin.max_deviation_bps = coin.max_deviation_bps.max(deviation);
+        }
+    }
+
+    /// Counts one transaction for a round that published prices, at its
+    /// full budget.
+    pub fn transaction(&mut self, gas_budget: u64) {
+        self.transactions += 1;
+        self.gas_budget += gas_budget;
+    }
+
+    pub fn print(&self) {
+        println!(
+            "rounds: {}, transactions: {}, gas budget: {}",
+            self.rounds, self.transactions, self.gas_budget
+        );
+        println!(
+            "{:<10} {:>7} {:>9} {:>7} {:>14} {:>14} {:>14} {:>10} {:>10}",
+            "coin",
+            "rounds",
+            "published",
+            "skipped",
+            "min",
+            "max",
+            "last",
+            "mean bps",
+            "max bps"
+        );
+        let dash = String::from("-");
+        for (symbol, c) in self.coins.iter() {
+            let fmt = |v: Option<Decimal>| {
+                v.map(|v| v.round_dp(8).to_string())
+                    .unwrap_or_else(|| dash.clone())
+            };
+            println!(
+                "{:<10} {:>7} {:>9} {:>7} {:>14} {:>14} {:>14} {:>10} {:>10}",
+                symbol,
+                c.rounds,
+                c.published,
+                c.skipped,
+                fmt(c.min),
+                fmt(c.max),
+                fmt(c.last),
+                c.mean_deviation_bps().round_dp(2),
+                c.max_deviation_bps.round_dp(2)
+            );
+        }
+    }
+}
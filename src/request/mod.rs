@@ -1,13 +1,39 @@
 use anyhow::{Ok, Result};
+use lazy_static::lazy_static;
 use reqwest;
 use reqwest::Response;
 use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: u64 = 10;
+const REQUEST_TIMEOUT: u64 = 30;
+
+lazy_static! {
+    /// Shared by all requests, so a hung endpoint fails in bounded time
+    /// instead of stalling the round.
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT))
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+        .build()
+        .unwrap();
+}
 
 pub async fn request<T>(url: &String) -> Result<T>
 where
     T: DeserializeOwned,
 {
-    let response: Response = reqwest::get(url).await?;
+    let response: Response = CLIENT.get(url).send().await?;
+    let response_json: T = response.json().await?;
+    Ok(response_json)
+}
+
+pub async fn post<B, T>(url: &String, body: &B) -> Result<T>
+where
+    B: Serialize,
+    T: DeserializeOwned,
+{
+    let response: Response = CLIENT.post(url).json(body).send().await?;
     let response_json: T = response.json().await?;
     Ok(response_json)
 }
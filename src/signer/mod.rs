@@ -0,0 +1,112 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bip32::DerivationPath;
+use bip39::{Language, Mnemonic, Seed};
+use std::str::FromStr;
+use std::time::Duration;
+use sui_keys::key_derive;
+use sui_sdk::types::base_types::SuiAddress;
+use sui_types::crypto::{
+    EncodeDecodeBase64, Signature, SignatureScheme, SuiKeyPair,
+};
+use sui_types::transaction::TransactionData;
+
+use crate::configuration::SignerConfig;
+use crate::cosign::{self, CosignRequest};
+use crate::mov;
+
+pub const DEFAULT_TIMEOUT: u64 = 10;
+
+/// Signs sui transactions for one address, with a key held in this process
+/// or elsewhere.
+#[async_trait]
+pub trait Signer: Send + Sync {
+    fn address(&self) -> SuiAddress;
+
+    /// Signs with the sui transaction intent.
+    async fn sign(&self, tx_data: &TransactionData) -> Result<Signature>;
+}
+
+/// A key held in memory only, never written to a keystore file.
+pub struct LocalSigner {
+    address: SuiAddress,
+    keypair: SuiKeyPair,
+}
+
+impl LocalSigner {
+    /// From a base64 `flag || private key`.
+    pub fn from_key(key: &str) -> Result<LocalSigner> {
+        let keypair = SuiKeyPair::decode_base64(key)
+            .map_err(|e| anyhow!("invalid key: {}", e))?;
+        let address = SuiAddress::from(&keypair.public());
+        Ok(LocalSigner { address, keypair })
+    }
+
+    /// Derives the key along `path`, or the scheme's default path.
+    pub fn from_mnemonic(
+        phrase: &str,
+        scheme: SignatureScheme,
+        path: Option<DerivationPath>,
+    ) -> Result<LocalSigner> {
+        let mnemonic = Mnemonic::from_phrase(phrase, Language::English)
+            .map_err(|e| anyhow!("invalid mnemonic: {}", e))?;
+        let seed = Seed::new(&mnemonic, "");
+        let (address, keypair) = key_derive::derive_key_pair_from_path(
+            seed.as_bytes(),
+            path,
+            &scheme,
+        )
+        .map_err(|e| anyhow!("derive key: {}", e))?;
+        Ok(LocalSigner { address, keypair })
+    }
+}
+
+#[async_trait]
+impl Signer for LocalSigner {
+    fn address(&self) -> SuiAddress {
+        self.address
+    }
+
+    async fn sign(&self, tx_data: &TransactionData) -> Result<Signature> {
+        Ok(mov::partial_signature(tx_data, &self.keypair))
+    }
+}
+
+/// A separate process holding the key, e.g. a hardened signer or an hsm
+/// front. It speaks the co-signer protocol without a price policy.
+pub struct RemoteSigner {
+    url: String,
+    address: SuiAddress,
+    timeout: Duration,
+}
+
+impl RemoteSigner {
+    pub fn new(cfg: &SignerConfig) -> Result<RemoteSigner> {
+        Ok(RemoteSigner {
+            url: cfg.url.clone(),
+            address: SuiAddress::from_str(&cfg.address)?,
+            timeout: Duration::from_secs(
+                cfg.timeout.unwrap_or(DEFAULT_TIMEOUT),
+            ),
+        })
+    }
+}
+
+#[async_trait]
+impl Signer for RemoteSigner {
+    fn address(&self) -> SuiAddress {
+        self.address
+    }
+
+    async fn sign(&self, tx_data: &TransactionData) -> Result<Signature> {
+        let request = CosignRequest {
+            tx_data: tx_data.clone(),
+        };
+        tokio::time::timeout(
+            self.timeout,
+            cosign::request_signature(&self.url, &request, self.address),
+        )
+        .await
+        .map_err(|_| anyhow!("signer {} timed out", self.url))?
+    }
+}
@@ -0,0 +1,578 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use ethers_core::abi::{self, Token};
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::{
+    Address, Bytes, Eip1559TransactionRequest, H256, U256,
+};
+use ethers_core::utils;
+use ethers_signers::{LocalWallet, Signer};
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+use super::{Batch, PriceSink};
+use crate::configuration::EvmSinkConfig;
+use crate::misc;
+
+pub const DEFAULT_FUNCTION: &str = "updatePrices";
+pub const DEFAULT_KEY_ENV: &str = "EVM_KEY";
+pub const ETH_DECIMALS: u32 = 18;
+const DEFAULT_STUCK_AFTER: u64 = 60;
+const DEFAULT_FEE_BUMP: u64 = 12;
+const MIN_FEE_BUMP: u64 = 10;
+
+/// A sent transaction that has not been mined yet.
+#[derive(Debug, Clone)]
+struct Pending {
+    nonce: U256,
+    /// The first transaction and its replacements. Any of them may be the
+    /// one mined.
+    hashes: Vec<H256>,
+    sent_at: u64,
+    max_fee: U256,
+    priority_fee: U256,
+}
+
+impl Pending {
+    fn hash(&self) -> H256 {
+        *self.hashes.last().unwrap()
+    }
+}
+
+pub struct EvmSink {
+    name: String,
+    rpc: String,
+    chain_id: u64,
+    contract: Address,
+    selector: [u8; 4],
+    getter: Option<[u8; 4]>,
+    wallet: LocalWallet,
+    gas_limit: Option<U256>,
+    priority_fee: Option<U256>,
+    max_fee: Option<U256>,
+    stuck_after: u64,
+    fee_bump: u64,
+    nonce: Option<U256>,
+    pending: Option<Pending>,
+    id: u64,
+}
+
+fn parse_u256(v: &Value) -> Result<U256> {
+    let s = v.as_str().ok_or(anyhow!("{} is not a quantity", v))?;
+    Ok(U256::from_str_radix(s.trim_start_matches("0x"), 16)?)
+}
+
+fn bump(fee: U256, percent: u64) -> U256 {
+    fee * (100 + percent) / 100 + 1
+}
+
+/// Encodes `function(uint8[],uint256[],uint64[])` for the batch.
+pub fn encode_batch(selector: [u8; 4], batch: &Batch) -> Bytes {
+    let ids = batch
+        .coin_idxs
+        .iter()
+        .map(|i| Token::Uint(U256::from(*i)))
+        .collect();
+    let prices = batch
+        .prices
+        .iter()
+        .map(|p| Token::Uint(U256::from(*p)))
+        .collect();
+    let ts = Token::Uint(U256::from(batch.ts / 1000));
+    let timestamps = vec![ts; batch.coin_idxs.len()];
+
+    let mut data = selector.to_vec();
+    data.extend(abi::encode(&[
+        Token::Array(ids),
+        Token::Array(prices),
+        Token::Array(timestamps),
+    ]));
+    data.into()
+}
+
+impl EvmSink {
+    pub fn new(cfg: &EvmSinkConfig, key: &str) -> Result<EvmSink> {
+        let wallet = LocalWallet::from_str(key.trim_start_matches("0x"))?
+            .with_chain_id(cfg.chain_id);
+        let function = cfg.function.as_deref().unwrap_or(DEFAULT_FUNCTION);
+        let signature = format!("{}(uint8[],uint256[],uint64[])", function);
+        let getter = cfg
+            .getter
+            .as_ref()
+            .map(|g| utils::id(format!("{}(uint8)", g)));
+        info!("{}: evm address {:?}", cfg.name, wallet.address());
+
+        Ok(EvmSink {
+            name: cfg.name.clone(),
+            rpc: cfg.rpc.clone(),
+            chain_id: cfg.chain_id,
+            contract: Address::from_str(&cfg.contract)?,
+            selector: utils::id(signature),
+            getter,
+            wallet,
+            gas_limit: cfg.gas_limit.map(U256::from),
+            priority_fee: cfg.priority_fee.map(U256::from),
+            max_fee: cfg.max_fee.map(U256::from),
+            stuck_after: cfg.stuck_after.unwrap_or(DEFAULT_STUCK_AFTER) * 1000,
+            fee_bump: cfg
+                .fee_bump
+                .unwrap_or(DEFAULT_FEE_BUMP)
+                .max(MIN_FEE_BUMP),
+            nonce: None,
+            pending: None,
+            id: 0,
+        })
+    }
+
+    async fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        self.id += 1;
+        super::json_rpc(&self.rpc, self.id, method, params).await
+    }
+
+    fn address(&self) -> String {
+        format!("{:?}", self.wallet.address())
+    }
+
+    async fn next_nonce(&mut self) -> Result<U256> {
+        if let Some(nonce) = self.nonce {
+            return Ok(nonce);
+        }
+        let params = json!([self.address(), "pending"]);
+        let nonce =
+            parse_u256(&self.call("eth_getTransactionCount", params).await?)?;
+        self.nonce = Some(nonce);
+        Ok(nonce)
+    }
+
+    /// Returns the max fee and priority fee for a new transaction.
+    async fn fees(&mut self) -> Result<(U256, U256)> {
+        let block = self
+            .call("eth_getBlockByNumber", json!(["latest", false]))
+            .await?;
+        let base_fee = parse_u256(
+            block
+                .get("baseFeePerGas")
+                .ok_or(anyhow!("chain has no base fee"))?,
+        )?;
+        let priority_fee = match self.priority_fee {
+            Some(fee) => fee,
+            None => parse_u256(
+                &self.call("eth_maxPriorityFeePerGas", json!([])).await?,
+            )?,
+        };
+        let mut max_fee: U256 = base_fee * 2 + priority_fee;
+        if let Some(cap) = self.max_fee {
+            max_fee = max_fee.min(cap);
+        }
+        Ok((max_fee, priority_fee.min(max_fee)))
+    }
+
+    async fn gas_limit(&mut self, data: &Bytes) -> Result<U256> {
+        if let Some(limit) = self.gas_limit {
+            return Ok(limit);
+        }
+        let params = json!([{
+            "from": self.address(),
+            "to": format!("{:?}", self.contract),
+            "data": data,
+        }]);
+        let estimate =
+            parse_u256(&self.call("eth_estimateGas", params).await?)?;
+        Ok(estimate * 12 / 10)
+    }
+
+    /// Clears the pending transaction once its nonce is used, by whichever
+    /// version got mined. Returns true while it is still waiting.
+    async fn poll_pending(&mut self) -> Result<bool> {
+        let pending = match &self.pending {
+            Some(p) => p.clone(),
+            None => return Ok(false),
+        };
+        let params = json!([self.address(), "latest"]);
+        let mined =
+            parse_u256(&self.call("eth_getTransactionCount", params).await?)?;
+        if mined <= pending.nonce {
+            return Ok(true);
+        }
+
+        for hash in pending.hashes.iter() {
+            let params = json!([format!("{:?}", hash)]);
+            let receipt =
+                self.call("eth_getTransactionReceipt", params).await?;
+            if receipt.is_null() {
+                continue;
+            }
+            if receipt.get("status").and_then(|s| s.as_str()) != Some("0x1") {
+                warn!("{}: tx {:?} reverted", self.name, hash);
+            }
+            break;
+        }
+        self.pending = None;
+        Ok(false)
+    }
+
+    async fn send(
+        &mut self,
+        data: Bytes,
+        nonce: U256,
+        max_fee: U256,
+        priority_fee: U256,
+    ) -> Result<H256> {
+        let gas = self.gas_limit(&data).await?;
+        let request = Eip1559TransactionRequest::new()
+            .from(self.wallet.address())
+            .to(self.contract)
+            .data(data)
+            .nonce(nonce)
+            .gas(gas)
+            .max_fee_per_gas(max_fee)
+            .max_priority_fee_per_gas(priority_fee)
+            .chain_id(self.chain_id);
+        let tx = TypedTransaction::Eip1559(request);
+        let signature = self.wallet.sign_transaction_sync(&tx)?;
+        let raw = tx.rlp_signed(&signature);
+
+        let result = self.call("eth_sendRawTransaction", json!([raw])).await;
+        let hash = match result {
+            Ok(hash) => hash,
+            Err(e) => {
+                // resync on the next round, e.g. after "nonce too low"
+                self.nonce = None;
+                return Err(e);
+            }
+        };
+        let hash = H256::from_str(
+            hash.as_str().ok_or(anyhow!("bad tx hash {}", hash))?,
+        )?;
+
+        let mut hashes = match self.pending.take() {
+            Some(p) if p.nonce == nonce => p.hashes,
+            _ => vec![],
+        };
+        hashes.push(hash);
+        self.pending = Some(Pending {
+            nonce,
+            hashes,
+            sent_at: misc::get_timestamp(),
+            max_fee,
+            priority_fee,
+        });
+        Ok(hash)
+    }
+}
+
+#[async_trait]
+impl PriceSink for EvmSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sends one transaction at a time. A transaction pending for longer
+    /// than `stuck_after` is replaced at the same nonce by this batch with
+    /// bumped fees; a younger one makes the round skip.
+    async fn publish(&mut self, batch: &Batch) -> Result<String> {
+        let data = encode_batch(self.selector, batch);
+
+        if self.poll_pending().await? {
+            let pending = self.pending.clone().unwrap();
+            let age = misc::get_timestamp().saturating_sub(pending.sent_at);
+            if age < self.stuck_after {
+                return Err(anyhow!("tx {:?} still pending", pending.hash()));
+            }
+
+            let (max_fee, priority_fee) = self.fees().await?;
+            let max_fee = max_fee.max(bump(pending.max_fee, self.fee_bump));
+            let priority_fee =
+                priority_fee.max(bump(pending.priority_fee, self.fee_bump));
+            if self.max_fee.map_or(false, |cap| max_fee > cap) {
+                return Err(anyhow!(
+                    "tx {:?} stuck at fee cap",
+                    pending.hash()
+                ));
+            }
+            warn!(
+                "{}: replacing stuck tx {:?}, nonce {}, max fee {}",
+                self.name,
+                pending.hash(),
+                pending.nonce,
+                max_fee
+            );
+            let hash = self
+                .send(data, pending.nonce, max_fee, priority_fee)
+                .await?;
+            return Ok(format!("{:?}", hash));
+        }
+
+        let (max_fee, priority_fee) = self.fees().await?;
+        let nonce = self.next_nonce().await?;
+        let hash = self.send(data, nonce, max_fee, priority_fee).await?;
+        self.nonce = Some(nonce + 1);
+        Ok(format!("{:?}", hash))
+    }
+
+    async fn last_prices(
+        &mut self,
+        coin_idxs: &[u8],
+    ) -> Result<Vec<Option<u128>>> {
+        let getter = self
+            .getter
+            .ok_or(anyhow!("{}: getter not configured", self.name))?;
+        let mut prices = Vec::new();
+        for idx in coin_idxs.iter() {
+            let mut data = getter.to_vec();
+            data.extend(abi::encode(&[Token::Uint(U256::from(*idx))]));
+            let params = json!([{
+                "to": format!("{:?}", self.contract),
+                "data": Bytes::from(data),
+            }, "latest"]);
+            let value = parse_u256(&self.call("eth_call", params).await?)?;
+            prices.push(match value.is_zero() {
+                true => None,
+                false => u128::try_from(value).ok(),
+            });
+        }
+        Ok(prices)
+    }
+
+    async fn balance(&mut self) -> Result<u128> {
+        let params = json!([self.address(), "latest"]);
+        let balance = parse_u256(&self.call("eth_getBalance", params).await?)?;
+        Ok(u128::try_from(balance).unwrap_or(u128::MAX))
+    }
+
+    fn balance_decimals(&self) -> u32 {
+        ETH_DECIMALS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::types::Signature;
+    use ethers_core::utils::rlp::Rlp;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    const KEY: &str =
+        "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+    /// Chain state the mock node answers from.
+    #[derive(Default)]
+    struct Node {
+        mined_nonce: u64,
+        receipts: HashMap<String, Value>,
+        requests: Vec<Value>,
+    }
+
+    impl Node {
+        fn answer(&mut self, request: &Value) -> Value {
+            self.requests.push(request.clone());
+            let params = &request["params"];
+            match request["method"].as_str().unwrap() {
+                "eth_getTransactionCount" => match params[1].as_str() {
+                    Some("latest") => json!(format!("{:#x}", self.mined_nonce)),
+                    _ => json!("0x5"),
+                },
+                "eth_getBlockByNumber" => {
+                    json!({"baseFeePerGas": "0x3b9aca00"})
+                }
+                "eth_maxPriorityFeePerGas" => json!("0x77359400"),
+                "eth_estimateGas" => json!("0x186a0"),
+                "eth_sendRawTransaction" => {
+                    let raw = params[0].as_str().unwrap();
+                    let raw =
+                        hex::decode(raw.trim_start_matches("0x")).unwrap();
+                    json!(format!("{:?}", H256::from(utils::keccak256(raw))))
+                }
+                "eth_getTransactionReceipt" => self
+                    .receipts
+                    .get(params[0].as_str().unwrap())
+                    .cloned()
+                    .unwrap_or(Value::Null),
+                method => panic!("unexpected {}", method),
+            }
+        }
+
+        fn count(&self, method: &str) -> usize {
+            self.requests
+                .iter()
+                .filter(|r| r["method"] == method)
+                .count()
+        }
+
+        fn sent(&self) -> Vec<(TypedTransaction, Signature)> {
+            self.requests
+                .iter()
+                .filter(|r| r["method"] == "eth_sendRawTransaction")
+                .map(|r| {
+                    let raw = r["params"][0].as_str().unwrap();
+                    let raw = hex::decode(&raw[2..]).unwrap();
+                    TypedTransaction::decode_signed(&Rlp::new(&raw)).unwrap()
+                })
+                .collect()
+        }
+    }
+
+    /// Serves json-rpc over http/1.1, one request per connection.
+    async fn serve(node: Arc<Mutex<Node>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    let line = line.trim_end().to_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(v) = line.strip_prefix("content-length:") {
+                        length = v.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0u8; length];
+                reader.read_exact(&mut body).await.unwrap();
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                let result = node.lock().unwrap().answer(&request);
+                let response = json!({
+                    "jsonrpc": "2.0",
+                    "id": request["id"],
+                    "result": result,
+                })
+                .to_string();
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    response.len()
+                );
+                let stream = reader.get_mut();
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        url
+    }
+
+    fn sink(rpc: String, stuck_after: u64) -> EvmSink {
+        let cfg = EvmSinkConfig {
+            name: "evm".to_owned(),
+            rpc,
+            chain_id: 31337,
+            contract: "0x5FbDB2315678afecb367f032d93F642f64180aa3".to_owned(),
+            function: None,
+            getter: None,
+            key_env: None,
+            gas_limit: None,
+            priority_fee: None,
+            max_fee: None,
+            stuck_after: Some(stuck_after),
+            fee_bump: None,
+        };
+        EvmSink::new(&cfg, KEY).unwrap()
+    }
+
+    fn batch() -> Batch {
+        Batch {
+            round: 1,
+            ts: 3000,
+            coin_idxs: vec![1],
+            prices: vec![2],
+        }
+    }
+
+    fn word(v: u64) -> String {
+        format!("{:064x}", v)
+    }
+
+    #[test]
+    fn encode_batch_abi() {
+        let selector = utils::id("updatePrices(uint8[],uint256[],uint64[])");
+        let data = encode_batch(selector, &batch());
+        let expected = [
+            hex::encode(selector),
+            // offsets of the three arrays
+            word(0x60),
+            word(0xa0),
+            word(0xe0),
+            // length and elements; the timestamp is in seconds
+            word(1),
+            word(1),
+            word(1),
+            word(2),
+            word(1),
+            word(3),
+        ]
+        .concat();
+        assert_eq!(hex::encode(&data), expected);
+    }
+
+    #[tokio::test]
+    async fn nonce_bump_and_replace() {
+        let node = Arc::new(Mutex::new(Node {
+            mined_nonce: 5,
+            ..Default::default()
+        }));
+        let mut sink = sink(serve(node.clone()).await, 0);
+
+        let first = sink.publish(&batch()).await.unwrap();
+        let sent = node.lock().unwrap().sent();
+        assert_eq!(sent[0].0.nonce().unwrap().as_u64(), 5);
+        let TypedTransaction::Eip1559(original) = &sent[0].0 else {
+            panic!("not eip-1559")
+        };
+        assert_eq!(
+            original.max_priority_fee_per_gas,
+            Some(2_000_000_000u64.into())
+        );
+        assert_eq!(original.max_fee_per_gas, Some(4_000_000_000u64.into()));
+        let from = sent[0].1.recover(sent[0].0.sighash()).unwrap();
+        assert_eq!(from, sink.wallet.address());
+
+        // Still pending past stuck_after: replaced at the same nonce.
+        sink.publish(&batch()).await.unwrap();
+        let sent = node.lock().unwrap().sent();
+        assert_eq!(sent[1].0.nonce().unwrap().as_u64(), 5);
+        let TypedTransaction::Eip1559(replacement) = &sent[1].0 else {
+            panic!("not eip-1559")
+        };
+        assert!(
+            replacement.max_fee_per_gas.unwrap()
+                > original.max_fee_per_gas.unwrap() * 11 / 10
+        );
+
+        // The original is the one mined; the replacement never gets a
+        // receipt. The next batch goes out at the next nonce.
+        {
+            let mut node = node.lock().unwrap();
+            node.mined_nonce = 6;
+            node.receipts.insert(first, json!({"status": "0x1"}));
+        }
+        sink.publish(&batch()).await.unwrap();
+        let sent = node.lock().unwrap().sent();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[2].0.nonce().unwrap().as_u64(), 6);
+        assert!(sink.pending.as_ref().unwrap().hashes.len() == 1);
+    }
+
+    #[tokio::test]
+    async fn young_pending_skips_without_fees() {
+        let node = Arc::new(Mutex::new(Node {
+            mined_nonce: 5,
+            ..Default::default()
+        }));
+        let mut sink = sink(serve(node.clone()).await, 60);
+
+        sink.publish(&batch()).await.unwrap();
+        let fee_calls = node.lock().unwrap().count("eth_getBlockByNumber");
+        assert!(sink.publish(&batch()).await.is_err());
+        let node = node.lock().unwrap();
+        assert_eq!(node.count("eth_getBlockByNumber"), fee_calls);
+        assert_eq!(node.sent().len(), 1);
+    }
+}
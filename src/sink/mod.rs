@@ -0,0 +1,86 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::request;
+
+pub mod evm;
+pub mod starknet;
+pub mod sui;
+
+/// One round of prices, ready to publish.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    pub round: u64,
+    pub ts: u64,
+    pub coin_idxs: Vec<u8>,
+    pub prices: Vec<u128>,
+}
+
+/// A refill of a sink's account, in the smallest unit of the chain.
+#[derive(Debug, Clone)]
+pub struct TopUp {
+    pub amount: u128,
+    pub digest: Option<String>,
+}
+
+/// The transaction landed, but reading the target back does not show what
+/// was sent. Not worth a resend: the digest exists and gas is spent.
+#[derive(Debug, Error)]
+#[error("tx {digest} read back: {reason}")]
+pub struct VerifyError {
+    pub digest: String,
+    pub reason: String,
+}
+
+/// A publishing target: a chain plus the oracle object or contract on it.
+#[async_trait]
+pub trait PriceSink: Send {
+    fn name(&self) -> &str;
+
+    /// Publishes the batch and returns the transaction digest.
+    async fn publish(&mut self, batch: &Batch) -> Result<String>;
+
+    /// Reads the prices currently stored on-chain, `None` for coins the
+    /// target has no price for.
+    async fn last_prices(
+        &mut self,
+        coin_idxs: &[u8],
+    ) -> Result<Vec<Option<u128>>>;
+
+    /// Returns the balance available for fees, in the smallest unit of the
+    /// chain.
+    async fn balance(&mut self) -> Result<u128>;
+
+    /// Decimals of the fee token, e.g. 9 for sui and 18 for ether.
+    fn balance_decimals(&self) -> u32;
+
+    /// Refills the account from its treasury when it runs low.
+    async fn top_up(&mut self) -> Result<Option<TopUp>> {
+        Ok(None)
+    }
+}
+
+/// Calls a json-rpc 2.0 method and returns its result.
+pub(crate) async fn json_rpc(
+    url: &String,
+    id: u64,
+    method: &str,
+    params: Value,
+) -> Result<Value> {
+    let body = json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": method,
+        "params": params,
+    });
+    let response: Value = request::post(url, &body).await?;
+    if let Some(error) = response.get("error") {
+        return Err(anyhow!("{}: {}", method, error));
+    }
+    response
+        .get("result")
+        .cloned()
+        .ok_or(anyhow!("{}: no result", method))
+}
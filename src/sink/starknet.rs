@@ -0,0 +1,448 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use starknet_core::crypto::{compute_hash_on_elements, ecdsa_sign};
+use starknet_core::types::FieldElement;
+use starknet_core::utils::{
+    cairo_short_string_to_felt, get_selector_from_name,
+};
+use starknet_crypto::get_public_key;
+
+use super::{Batch, PriceSink};
+use crate::configuration::StarknetSinkConfig;
+
+pub const DEFAULT_ENTRYPOINT: &str = "update_prices";
+pub const DEFAULT_KEY_ENV: &str = "STARKNET_KEY";
+pub const DEFAULT_FEE_TOKEN: &str =
+    "0x049d36570d4e46f48e99674bd3fcc84644ddd6b96f7c741b1562b82f9e004dc7";
+pub const FEE_DECIMALS: u32 = 18;
+const DEFAULT_FEE_MULTIPLIER_BPS: u64 = 15000;
+
+/// How prices are laid out in calldata.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// One felt per price.
+    #[default]
+    Felt,
+    /// A `(low, high)` pair per price, as cairo lays out `u256`.
+    U256,
+}
+
+pub struct StarknetSink {
+    name: String,
+    rpc: String,
+    account: FieldElement,
+    contract: FieldElement,
+    selector: FieldElement,
+    getter: Option<FieldElement>,
+    encoding: Encoding,
+    key: FieldElement,
+    fee_token: FieldElement,
+    fee_multiplier_bps: u64,
+    chain_id: Option<FieldElement>,
+    nonce: Option<FieldElement>,
+    id: u64,
+}
+
+fn hex(v: &FieldElement) -> String {
+    format!("{:#x}", v)
+}
+
+fn parse_felt(v: &Value) -> Result<FieldElement> {
+    let s = v.as_str().ok_or(anyhow!("{} is not a felt", v))?;
+    Ok(FieldElement::from_hex_be(s)?)
+}
+
+fn felt_to_u128(v: FieldElement) -> Result<u128> {
+    u128::try_from(v).map_err(|_| anyhow!("{:#x} exceeds u128", v))
+}
+
+/// `fee` times `bps` basis points, rounded up.
+fn apply_bps(fee: u128, bps: u64) -> Result<u128> {
+    let scaled = fee.checked_mul(bps as u128).ok_or(anyhow!(
+        "fee {} times {} bps overflows",
+        fee,
+        bps
+    ))?;
+    Ok(scaled / 10000 + (scaled % 10000 != 0) as u128)
+}
+
+/// Encodes `(ids, prices, timestamps)` as three cairo arrays.
+pub fn encode_batch(batch: &Batch, encoding: Encoding) -> Vec<FieldElement> {
+    let len = FieldElement::from(batch.coin_idxs.len());
+    let mut calldata = vec![len];
+    calldata.extend(batch.coin_idxs.iter().map(|i| FieldElement::from(*i)));
+
+    calldata.push(len);
+    for price in batch.prices.iter() {
+        calldata.push(FieldElement::from(*price));
+        if encoding == Encoding::U256 {
+            calldata.push(FieldElement::ZERO);
+        }
+    }
+
+    calldata.push(len);
+    let ts = FieldElement::from(batch.ts / 1000);
+    calldata.extend(vec![ts; batch.coin_idxs.len()]);
+    calldata
+}
+
+/// Wraps one call in the `__execute__` calldata of a cairo 1 account.
+fn execute_calldata(
+    contract: FieldElement,
+    selector: FieldElement,
+    calldata: Vec<FieldElement>,
+) -> Vec<FieldElement> {
+    let mut execute = vec![
+        FieldElement::ONE,
+        contract,
+        selector,
+        FieldElement::from(calldata.len()),
+    ];
+    execute.extend(calldata);
+    execute
+}
+
+/// Hash of an invoke v1 transaction. Fee estimates sign the query version.
+pub fn invoke_hash(
+    account: FieldElement,
+    calldata: &[FieldElement],
+    max_fee: FieldElement,
+    chain_id: FieldElement,
+    nonce: FieldElement,
+    query: bool,
+) -> FieldElement {
+    let prefix = cairo_short_string_to_felt("invoke").unwrap();
+    let version = match query {
+        true => {
+            FieldElement::from(2u128.pow(127)) * FieldElement::TWO
+                + FieldElement::ONE
+        }
+        false => FieldElement::ONE,
+    };
+    compute_hash_on_elements(&[
+        prefix,
+        version,
+        account,
+        FieldElement::ZERO,
+        compute_hash_on_elements(calldata),
+        max_fee,
+        chain_id,
+        nonce,
+    ])
+}
+
+impl StarknetSink {
+    pub fn new(cfg: &StarknetSinkConfig, key: &str) -> Result<StarknetSink> {
+        let key = FieldElement::from_hex_be(key)?;
+        let entrypoint =
+            cfg.entrypoint.as_deref().unwrap_or(DEFAULT_ENTRYPOINT);
+        let getter = match &cfg.getter {
+            Some(g) => Some(get_selector_from_name(g)?),
+            None => None,
+        };
+        let fee_token = cfg.fee_token.as_deref().unwrap_or(DEFAULT_FEE_TOKEN);
+        info!(
+            "{}: starknet account {}, public key {:#x}",
+            cfg.name,
+            cfg.account,
+            get_public_key(&key)
+        );
+
+        Ok(StarknetSink {
+            name: cfg.name.clone(),
+            rpc: cfg.rpc.clone(),
+            account: FieldElement::from_hex_be(&cfg.account)?,
+            contract: FieldElement::from_hex_be(&cfg.contract)?,
+            selector: get_selector_from_name(entrypoint)?,
+            getter,
+            encoding: cfg.encoding.unwrap_or_default(),
+            key,
+            fee_token: FieldElement::from_hex_be(fee_token)?,
+            fee_multiplier_bps: cfg
+                .fee_multiplier_bps
+                .unwrap_or(DEFAULT_FEE_MULTIPLIER_BPS),
+            chain_id: None,
+            nonce: None,
+            id: 0,
+        })
+    }
+
+    async fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        self.id += 1;
+        super::json_rpc(&self.rpc, self.id, method, params).await
+    }
+
+    async fn chain_id(&mut self) -> Result<FieldElement> {
+        if let Some(id) = self.chain_id {
+            return Ok(id);
+        }
+        let id = parse_felt(&self.call("starknet_chainId", json!([])).await?)?;
+        self.chain_id = Some(id);
+        Ok(id)
+    }
+
+    /// The node's pending nonce, unless ours is ahead of it.
+    async fn next_nonce(&mut self) -> Result<FieldElement> {
+        let params = json!(["pending", hex(&self.account)]);
+        let node = parse_felt(&self.call("starknet_getNonce", params).await?)?;
+        Ok(match self.nonce {
+            Some(local) if felt_to_u128(local)? > felt_to_u128(node)? => local,
+            _ => node,
+        })
+    }
+
+    fn invoke(
+        &self,
+        calldata: &[FieldElement],
+        max_fee: FieldElement,
+        chain_id: FieldElement,
+        nonce: FieldElement,
+        query: bool,
+    ) -> Result<Value> {
+        let hash = invoke_hash(
+            self.account,
+            calldata,
+            max_fee,
+            chain_id,
+            nonce,
+            query,
+        );
+        let signature = ecdsa_sign(&self.key, &hash)?;
+        let version = match query {
+            true => "0x100000000000000000000000000000001",
+            false => "0x1",
+        };
+        Ok(json!({
+            "type": "INVOKE",
+            "sender_address": hex(&self.account),
+            "calldata": calldata.iter().map(hex).collect::<Vec<_>>(),
+            "max_fee": hex(&max_fee),
+            "version": version,
+            "signature": [hex(&signature.r), hex(&signature.s)],
+            "nonce": hex(&nonce),
+        }))
+    }
+
+    async fn estimate_fee(
+        &mut self,
+        calldata: &[FieldElement],
+        chain_id: FieldElement,
+        nonce: FieldElement,
+    ) -> Result<FieldElement> {
+        let tx =
+            self.invoke(calldata, FieldElement::ZERO, chain_id, nonce, true)?;
+        let estimates = self
+            .call("starknet_estimateFee", json!([[tx], "pending"]))
+            .await?;
+        let fee = estimates
+            .get(0)
+            .and_then(|e| e.get("overall_fee"))
+            .ok_or(anyhow!("no fee estimate"))?;
+        let fee = felt_to_u128(parse_felt(fee)?)?;
+        Ok(FieldElement::from(apply_bps(fee, self.fee_multiplier_bps)?))
+    }
+
+    async fn view(
+        &mut self,
+        contract: FieldElement,
+        selector: FieldElement,
+        calldata: Vec<FieldElement>,
+    ) -> Result<Vec<FieldElement>> {
+        let request = json!({
+            "contract_address": hex(&contract),
+            "entry_point_selector": hex(&selector),
+            "calldata": calldata.iter().map(hex).collect::<Vec<_>>(),
+        });
+        let result = self
+            .call("starknet_call", json!([request, "latest"]))
+            .await?;
+        result
+            .as_array()
+            .ok_or(anyhow!("bad call result {}", result))?
+            .iter()
+            .map(parse_felt)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl PriceSink for StarknetSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn publish(&mut self, batch: &Batch) -> Result<String> {
+        let calldata = execute_calldata(
+            self.contract,
+            self.selector,
+            encode_batch(batch, self.encoding),
+        );
+        let chain_id = self.chain_id().await?;
+        let nonce = self.next_nonce().await?;
+        let max_fee = self.estimate_fee(&calldata, chain_id, nonce).await?;
+
+        let tx = self.invoke(&calldata, max_fee, chain_id, nonce, false)?;
+        let result = match self
+            .call("starknet_addInvokeTransaction", json!([tx]))
+            .await
+        {
+            Ok(result) => result,
+            Err(e) => {
+                self.nonce = None;
+                return Err(e);
+            }
+        };
+        self.nonce = Some(nonce + FieldElement::ONE);
+
+        result
+            .get("transaction_hash")
+            .and_then(|h| h.as_str())
+            .map(|h| h.to_owned())
+            .ok_or(anyhow!("no transaction hash in {}", result))
+    }
+
+    async fn last_prices(
+        &mut self,
+        coin_idxs: &[u8],
+    ) -> Result<Vec<Option<u128>>> {
+        let getter = self
+            .getter
+            .ok_or(anyhow!("{}: getter not configured", self.name))?;
+        let mut prices = Vec::new();
+        for idx in coin_idxs.iter() {
+            let result = self
+                .view(self.contract, getter, vec![FieldElement::from(*idx)])
+                .await?;
+            let price = match (self.encoding, result.as_slice()) {
+                (Encoding::U256, [low, high, ..])
+                    if *high == FieldElement::ZERO =>
+                {
+                    Some(felt_to_u128(*low)?)
+                }
+                (Encoding::Felt, [price, ..]) => Some(felt_to_u128(*price)?),
+                _ => None,
+            };
+            prices.push(price.filter(|p| *p != 0));
+        }
+        Ok(prices)
+    }
+
+    /// Fee token balance, read through its `balanceOf`.
+    async fn balance(&mut self) -> Result<u128> {
+        let selector = get_selector_from_name("balanceOf")?;
+        let result = self
+            .view(self.fee_token, selector, vec![self.account])
+            .await?;
+        match result.as_slice() {
+            [low, high, ..] if *high == FieldElement::ZERO => {
+                felt_to_u128(*low)
+            }
+            [low] => felt_to_u128(*low),
+            _ => Err(anyhow!("bad balance {:?}", result)),
+        }
+    }
+
+    fn balance_decimals(&self) -> u32 {
+        FEE_DECIMALS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn felt(v: u128) -> FieldElement {
+        FieldElement::from(v)
+    }
+
+    fn batch() -> Batch {
+        Batch {
+            round: 1,
+            ts: 1700000000123,
+            coin_idxs: vec![1, 3],
+            prices: vec![100, (1 << 64) + 5],
+        }
+    }
+
+    #[test]
+    fn encode_batch_arrays() {
+        let ts = felt(1700000000);
+        let big = felt((1 << 64) + 5);
+        assert_eq!(
+            encode_batch(&batch(), Encoding::Felt),
+            vec![
+                felt(2),
+                felt(1),
+                felt(3),
+                felt(2),
+                felt(100),
+                big,
+                felt(2),
+                ts,
+                ts
+            ]
+        );
+        let zero = FieldElement::ZERO;
+        assert_eq!(
+            encode_batch(&batch(), Encoding::U256)[3..8],
+            [felt(2), felt(100), zero, big, zero]
+        );
+    }
+
+    #[test]
+    fn execute_calldata_layout() {
+        let inner = encode_batch(&batch(), Encoding::Felt);
+        let calldata = execute_calldata(felt(0x5678), felt(0x9abc), inner);
+        assert_eq!(
+            calldata[..4],
+            [felt(1), felt(0x5678), felt(0x9abc), felt(9)]
+        );
+        assert_eq!(calldata.len(), 13);
+    }
+
+    /// Expected hashes were computed with an independent pedersen
+    /// implementation checked against the starkware test vectors.
+    #[test]
+    fn invoke_v1_hash() {
+        let inner = encode_batch(&batch(), Encoding::Felt);
+        let calldata = execute_calldata(felt(0x5678), felt(0x9abc), inner);
+        let chain_id = cairo_short_string_to_felt("SN_GOERLI").unwrap();
+        let hash = |max_fee, query| {
+            invoke_hash(
+                felt(0x1234),
+                &calldata,
+                max_fee,
+                chain_id,
+                felt(7),
+                query,
+            )
+        };
+
+        let expected = FieldElement::from_hex_be(
+            "0x368851f7eb0c026ba4ca49dfb9c4a5b43a8311b02243791f470714adcc0c9",
+        )
+        .unwrap();
+        assert_eq!(hash(felt(10_000_000_000_000_000), false), expected);
+
+        let expected = FieldElement::from_hex_be(
+            "0x3f7ceb4faabdaf2565f2d781d812027cc6c610eecaffeaadbff76f4ff870071",
+        )
+        .unwrap();
+        assert_eq!(hash(FieldElement::ZERO, true), expected);
+    }
+
+    #[test]
+    fn fee_multiplier_in_bps() {
+        assert_eq!(apply_bps(1000, 15000).unwrap(), 1500);
+        assert_eq!(apply_bps(1, 15000).unwrap(), 2);
+        assert_eq!(
+            apply_bps(u64::MAX as u128, 10000).unwrap(),
+            u64::MAX as u128
+        );
+        assert!(apply_bps(u128::MAX, 15000).is_err());
+    }
+}
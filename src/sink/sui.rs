@@ -0,0 +1,531 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use log::{error, info};
+use serde_json::Value;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use sui_json_rpc_types::{SuiObjectDataOptions, SuiParsedData};
+use sui_sdk::error::Error as SuiError;
+use sui_sdk::types::base_types::SuiAddress;
+use sui_types::base_types::{ObjectID, ObjectRef};
+use sui_types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+use sui_types::transaction::{
+    ProgrammableTransaction, TransactionData, VerifiedTransaction,
+};
+use tokio::sync::Mutex;
+
+use super::{Batch, PriceSink, TopUp, VerifyError};
+use crate::configuration::{DryRunConfig, GasPoolConfig};
+use crate::cosign::Cosigner;
+use crate::mov;
+use crate::mov::gas::GasPool;
+use crate::mov::rpc::RpcPool;
+use crate::mov::treasury::Treasury;
+use crate::mov::wallet::Wallet;
+use crate::prom;
+use crate::signer::Signer;
+
+pub const DEFAULT_MODULE: &str = "oracle";
+pub const DEFAULT_FUNCTION: &str = "update_token_price_batch";
+pub const SUI_DECIMALS: u32 = 9;
+pub const DEFAULT_GAS_MARGIN: f64 = 0.2;
+
+#[derive(Debug, Clone)]
+pub struct Multisig {
+    pub address: String,
+    pub gas: String,
+    pub publickeys: Vec<String>,
+    pub weights: Vec<u8>,
+    pub threshold: u16,
+    /// Asked for partial signatures when the local key's weight is short of
+    /// the threshold.
+    pub cosigners: Vec<Cosigner>,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub package_id: String,
+    pub module: String,
+    pub function: String,
+    pub oracle_cap: String,
+    pub price_oracle: String,
+    pub gas_budget: u64,
+    /// Defaults to the reference gas price.
+    pub gas_price: Option<u64>,
+    /// Simulate first and derive the budget, `gas_budget` being the cap.
+    pub dry_run: Option<DryRunConfig>,
+    /// Further `(oracle_cap, price_oracle)` pairs updated in the same
+    /// transaction.
+    pub extra_oracles: Vec<(String, String)>,
+    /// Json pointer to the price vector in the oracle object's fields.
+    pub prices_path: Option<String>,
+    /// Json pointer to the timestamp vector.
+    pub timestamps_path: Option<String>,
+    /// Read the oracle back after each update.
+    pub verify: bool,
+}
+
+pub struct SuiSink {
+    name: String,
+    wallet: Wallet,
+    signer: Box<dyn Signer>,
+    target: Target,
+    multisig: Option<Multisig>,
+    rpcs: RpcPool,
+    gas_pool: Option<GasPool>,
+    treasury: Option<Arc<Mutex<Treasury>>>,
+}
+
+/// Whether `e` came from reaching the rpc rather than from the transaction
+/// or its signing, so another endpoint may succeed.
+fn is_rpc_error(e: &anyhow::Error) -> bool {
+    e.chain().any(|c| {
+        matches!(
+            c.downcast_ref::<SuiError>(),
+            Some(SuiError::RpcError(_))
+                | Some(SuiError::FailToConfirmTransactionStatus(..))
+        )
+    })
+}
+
+fn json_to_u128(v: &Value) -> Option<u128> {
+    match v {
+        Value::String(s) => s.parse().ok(),
+        Value::Number(n) => n.as_u64().map(|n| n as u128),
+        _ => None,
+    }
+}
+
+/// Picks `coin_idxs` out of the array at `path`.
+fn read_array(
+    fields: &Value,
+    path: &str,
+    coin_idxs: &[u8],
+) -> Result<Vec<Option<u128>>> {
+    let values = fields
+        .pointer(path)
+        .and_then(|p| p.as_array())
+        .ok_or(anyhow!("path {} not found", path))?;
+    Ok(coin_idxs
+        .iter()
+        .map(|i| values.get(*i as usize).and_then(json_to_u128))
+        .collect())
+}
+
+impl SuiSink {
+    pub fn new(
+        name: &str,
+        wallet: Wallet,
+        signer: Box<dyn Signer>,
+        target: Target,
+        multisig: Option<Multisig>,
+        rpcs: RpcPool,
+    ) -> Result<SuiSink> {
+        if target.verify
+            && target.prices_path.is_none()
+            && target.timestamps_path.is_none()
+        {
+            return Err(anyhow!(
+                "{}: verify needs prices_path or timestamps_path",
+                name
+            ));
+        }
+        Ok(SuiSink {
+            name: name.to_owned(),
+            wallet,
+            signer,
+            target,
+            multisig,
+            rpcs,
+            gas_pool: None,
+            treasury: None,
+        })
+    }
+
+    /// Pays updates from a pool of gas coins of the sender instead of a
+    /// single coin.
+    pub fn with_gas_pool(mut self, cfg: &GasPoolConfig) -> Result<SuiSink> {
+        let owner = self.sender()?;
+        self.gas_pool = Some(GasPool::new(owner, cfg));
+        Ok(self)
+    }
+
+    /// Refills the sender from a treasury, which may be shared with other
+    /// sinks under one daily cap.
+    pub fn with_treasury(mut self, treasury: Arc<Mutex<Treasury>>) -> SuiSink {
+        self.treasury = Some(treasury);
+        self
+    }
+
+    fn sender(&self) -> Result<SuiAddress> {
+        match &self.multisig {
+            None => Ok(self.signer.address()),
+            Some(m) => Ok(SuiAddress::from_str(&m.address)?),
+        }
+    }
+
+    async fn sign(
+        &self,
+        tx_data: TransactionData,
+    ) -> Result<VerifiedTransaction> {
+        match &self.multisig {
+            None => mov::sign_transaction(self.signer.as_ref(), tx_data).await,
+            Some(m) => {
+                mov::sign_multisig_transaction(
+                    self.signer.as_ref(),
+                    tx_data,
+                    &m.publickeys,
+                    &m.weights,
+                    m.threshold,
+                    &m.cosigners,
+                    m.timeout,
+                )
+                .await
+            }
+        }
+    }
+
+    /// Moves to the best ranked rpc other than `skip`, passing over any
+    /// that fail to connect. False if none is left.
+    async fn use_best_rpc(&mut self, skip: Option<&str>) -> bool {
+        for rpc in self.rpcs.ranked() {
+            if Some(rpc.as_str()) == skip {
+                continue;
+            }
+            if rpc == self.wallet.rpc() {
+                return true;
+            }
+            match self.wallet.set_client(&rpc).await {
+                Ok(()) => {
+                    info!("{}: switched to rpc {}", self.name, rpc);
+                    return true;
+                }
+                Err(e) => {
+                    error!("{}: rpc {}: {}", self.name, rpc, e);
+                    self.rpcs.mark_failed(&rpc);
+                }
+            }
+        }
+        false
+    }
+
+    fn calls(&self) -> Result<Vec<mov::UpdateCall>> {
+        let t = &self.target;
+        let package = ObjectID::from_hex_literal(&t.package_id)?;
+        let mut oracles = vec![(&t.oracle_cap, &t.price_oracle)];
+        oracles.extend(t.extra_oracles.iter().map(|(c, o)| (c, o)));
+
+        let mut calls = Vec::new();
+        for (oracle_cap, price_oracle) in oracles {
+            calls.push(mov::UpdateCall {
+                package,
+                module: t.module.clone(),
+                function: t.function.clone(),
+                oracle_cap: ObjectID::from_hex_literal(oracle_cap)?,
+                price_oracle: ObjectID::from_hex_literal(price_oracle)?,
+            });
+        }
+        Ok(calls)
+    }
+
+    /// Dry runs the transaction at the maximum budget and returns the
+    /// simulated cost plus the margin. Aborts are decoded and refused.
+    async fn estimate_budget(
+        &self,
+        tx_data: TransactionData,
+        dry_run: &DryRunConfig,
+    ) -> Result<u64> {
+        let cost = match mov::dry_run(&self.wallet, tx_data).await {
+            Ok(cost) => cost,
+            Err(e) => {
+                if let Some(abort) = mov::decode_abort(&e.to_string()) {
+                    let reason = dry_run
+                        .abort_codes
+                        .as_ref()
+                        .and_then(|codes| codes.get(&abort.code))
+                        .map(|r| r.as_str())
+                        .unwrap_or("unknown");
+                    error!(
+                        "{}: {}::{} would abort with code {} ({})",
+                        self.name,
+                        abort.module.unwrap_or_default(),
+                        abort.function.unwrap_or_default(),
+                        abort.code,
+                        reason
+                    );
+                }
+                return Err(e);
+            }
+        };
+
+        let used = cost.computation_cost + cost.storage_cost;
+        let margin = dry_run.margin.unwrap_or(DEFAULT_GAS_MARGIN);
+        let budget = (used as f64 * (1.0 + margin)).ceil() as u64;
+        info!("{}: simulated gas {}, budget {}", self.name, used, budget);
+        if budget > self.target.gas_budget {
+            return Err(anyhow!(
+                "gas budget {} over the maximum {}",
+                budget,
+                self.target.gas_budget
+            ));
+        }
+        Ok(budget)
+    }
+
+    async fn send(&mut self, batch: &Batch) -> Result<String> {
+        let mut builder = ProgrammableTransactionBuilder::new();
+        for call in self.calls()?.iter() {
+            mov::add_update_call(
+                &self.wallet,
+                &mut builder,
+                call,
+                &batch.coin_idxs,
+                &batch.prices,
+                batch.ts,
+            )
+            .await?;
+        }
+        let pt = builder.finish();
+
+        let sender = self.sender()?;
+        let budget = self.target.gas_budget;
+        let gas = match &mut self.gas_pool {
+            Some(pool) => {
+                pool.refresh(&self.wallet).await?;
+                pool.pick(budget)?
+            }
+            None => {
+                let gas_id = match &self.multisig {
+                    None => None,
+                    Some(m) => Some(ObjectID::from_hex_literal(&m.gas)?),
+                };
+                mov::select_gas(&self.wallet, sender, budget, gas_id).await?
+            }
+        };
+
+        // The coin goes back to the pool whether or not the update landed.
+        let result = self.submit(sender, pt, gas).await;
+        if let Some(pool) = &mut self.gas_pool {
+            pool.release(&gas);
+        }
+        result
+    }
+
+    async fn submit(
+        &self,
+        sender: SuiAddress,
+        pt: ProgrammableTransaction,
+        gas: ObjectRef,
+    ) -> Result<String> {
+        let t = &self.target;
+        let gas_price = mov::get_gas_price(&self.wallet, t.gas_price).await?;
+
+        let mut gas_budget = t.gas_budget;
+        if let Some(dry_run) = &t.dry_run {
+            let tx_data = mov::build_transaction(
+                sender,
+                pt.clone(),
+                gas,
+                gas_budget,
+                gas_price,
+            );
+            gas_budget = self.estimate_budget(tx_data, dry_run).await?;
+        }
+        let tx_data =
+            mov::build_transaction(sender, pt, gas, gas_budget, gas_price);
+
+        let verified = self.sign(tx_data).await?;
+        let response = mov::execute(&self.wallet, verified).await?;
+        Ok(response.digest.to_string())
+    }
+
+    /// Merges dust and refills the gas pool, once per maintenance interval.
+    async fn maintain_gas(&mut self) -> Result<()> {
+        let due = match &mut self.gas_pool {
+            Some(pool) => pool.maintenance_due(),
+            None => false,
+        };
+        if !due {
+            return Ok(());
+        }
+
+        let budget = self.target.gas_budget;
+        let pool = self.gas_pool.as_mut().unwrap();
+        pool.refresh(&self.wallet).await?;
+        let (owner, (gas, pt)) = match pool.maintenance(budget)? {
+            Some(tx) => (pool.owner, tx),
+            None => return Ok(()),
+        };
+
+        let result = async {
+            let gas_price =
+                mov::get_gas_price(&self.wallet, self.target.gas_price).await?;
+            let tx_data =
+                mov::build_transaction(owner, pt, gas, budget, gas_price);
+            let verified = self.sign(tx_data).await?;
+            mov::execute(&self.wallet, verified).await
+        }
+        .await;
+        if let Some(pool) = &mut self.gas_pool {
+            pool.release(&gas);
+        }
+        let response = result?;
+        info!("{}: gas pool maintained in {}", self.name, response.digest);
+        Ok(())
+    }
+
+    async fn read_oracle(&self, price_oracle: &str) -> Result<Value> {
+        let id = ObjectID::from_hex_literal(price_oracle)?;
+        let client = self.wallet.get_client().await?;
+        let response = client
+            .read_api()
+            .get_object_with_options(
+                id,
+                SuiObjectDataOptions::new().with_content(),
+            )
+            .await?;
+        match response.data.and_then(|d| d.content) {
+            Some(SuiParsedData::MoveObject(object)) => {
+                Ok(object.fields.to_json_value())
+            }
+            _ => Err(anyhow!("price oracle {} has no move content", id)),
+        }
+    }
+
+    /// Reads every updated oracle back and compares it with what was sent.
+    async fn verify(&self, batch: &Batch) -> Result<()> {
+        let t = &self.target;
+        let mut oracles = vec![&t.price_oracle];
+        oracles.extend(t.extra_oracles.iter().map(|(_, o)| o));
+        for oracle in oracles {
+            self.verify_oracle(oracle, batch)
+                .await
+                .map_err(|e| anyhow!("{}: {}", oracle, e))?;
+        }
+        Ok(())
+    }
+
+    async fn verify_oracle(&self, oracle: &str, batch: &Batch) -> Result<()> {
+        let fields = self.read_oracle(oracle).await?;
+        if let Some(path) = &self.target.prices_path {
+            let stored = read_array(&fields, path, &batch.coin_idxs)?;
+            for (i, price) in batch.prices.iter().enumerate() {
+                if stored[i] != Some(*price) {
+                    return Err(anyhow!(
+                        "coin {} price {:?}, sent {}",
+                        batch.coin_idxs[i],
+                        stored[i],
+                        price
+                    ));
+                }
+            }
+        }
+        if let Some(path) = &self.target.timestamps_path {
+            let stored = read_array(&fields, path, &batch.coin_idxs)?;
+            for (i, ts) in stored.iter().enumerate() {
+                if *ts != Some(batch.ts as u128) {
+                    return Err(anyhow!(
+                        "coin {} timestamp {:?}, sent {}",
+                        batch.coin_idxs[i],
+                        ts,
+                        batch.ts
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PriceSink for SuiSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sends through the best rpc, and retries once on another one if the
+    /// rpc itself failed. Aborts, refusals and the like are not retried,
+    /// nor is a failed read back, which comes back as a `VerifyError`.
+    async fn publish(&mut self, batch: &Batch) -> Result<String> {
+        self.use_best_rpc(None).await;
+        let result = match self.send(batch).await {
+            Err(e) if is_rpc_error(&e) => {
+                let failed = self.wallet.rpc().to_owned();
+                error!("{}: call via {}: {}", self.name, failed, e);
+                self.rpcs.mark_failed(&failed);
+                match self.use_best_rpc(Some(&failed)).await {
+                    true => self.send(batch).await,
+                    false => Err(e),
+                }
+            }
+            result => result,
+        };
+        if let Ok(digest) = &result {
+            info!("{}: tx {} via {}", self.name, digest, self.wallet.rpc());
+            prom::count_rpc_transaction(&self.name, self.wallet.rpc());
+        }
+        if let Err(e) = self.maintain_gas().await {
+            error!("{}: gas pool: {}", self.name, e);
+        }
+        let digest = result?;
+        if self.target.verify {
+            if let Err(e) = self.verify(batch).await {
+                return Err(VerifyError {
+                    digest,
+                    reason: e.to_string(),
+                }
+                .into());
+            }
+        }
+        Ok(digest)
+    }
+
+    async fn last_prices(
+        &mut self,
+        coin_idxs: &[u8],
+    ) -> Result<Vec<Option<u128>>> {
+        let path = self
+            .target
+            .prices_path
+            .as_ref()
+            .ok_or(anyhow!("{}: prices_path not configured", self.name))?;
+        let fields = self.read_oracle(&self.target.price_oracle).await?;
+        read_array(&fields, path, coin_idxs)
+    }
+
+    /// Every coin of the sender counts, except for a multisig without a gas
+    /// pool, which only ever pays with its fixed gas coin.
+    async fn balance(&mut self) -> Result<u128> {
+        let balance = match (&self.multisig, &self.gas_pool) {
+            (Some(m), None) => {
+                mov::get_multi_balance(&self.wallet, &m.address, &m.gas).await?
+            }
+            _ => {
+                mov::get_total_gas_balance(&self.wallet, &self.sender()?)
+                    .await?
+            }
+        };
+        Ok(balance as u128)
+    }
+
+    fn balance_decimals(&self) -> u32 {
+        SUI_DECIMALS
+    }
+
+    async fn top_up(&mut self) -> Result<Option<TopUp>> {
+        if self.treasury.is_none() {
+            return Ok(None);
+        }
+        let balance = self.balance().await? as u64;
+        let recipient = self.sender()?;
+        let mut treasury = self.treasury.as_ref().unwrap().lock().await;
+        let transfer =
+            treasury.top_up(&self.wallet, recipient, balance).await?;
+        Ok(transfer.map(|t| TopUp {
+            amount: t.amount as u128,
+            digest: t.digest,
+        }))
+    }
+}
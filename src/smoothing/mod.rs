@@ -0,0 +1,264 @@
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use crate::decimal::Decimal;
+
+const HOUR: u64 = 60 * 60 * 1000;
+const DAY: u64 = 24 * HOUR;
+
+/// Per-coin smoothing applied to the aggregated price before publishing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Method {
+    /// Exponential moving average. `half_life` is in seconds.
+    Ema { half_life: u64 },
+    /// Time-weighted average over the last `rounds` rounds.
+    Twap { rounds: usize },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Sample {
+    pub ts: u64,
+    pub price: Decimal,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    pub samples: HashMap<String, VecDeque<Sample>>,
+    pub ema: HashMap<String, Sample>,
+}
+
+/// What is on disk: the smoothing history and, apart from it, the
+/// published prices the bounds check compares against.
+#[derive(Debug, Default, Deserialize)]
+struct Saved {
+    #[serde(flatten)]
+    history: History,
+    #[serde(default)]
+    published: HashMap<String, VecDeque<Sample>>,
+}
+
+#[derive(Serialize)]
+struct SavedRef<'a> {
+    #[serde(flatten)]
+    history: &'a History,
+    published: &'a HashMap<String, VecDeque<Sample>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Smoother {
+    pub path: String,
+    pub history: History,
+    /// Hourly samples of published prices over the last day.
+    pub published: HashMap<String, VecDeque<Sample>>,
+}
+
+fn ema(prev: Option<Sample>, current: Sample, half_life: u64) -> Decimal {
+    let prev = match prev {
+        Some(p) if p.ts < current.ts && half_life > 0 => p,
+        _ => return current.price,
+    };
+
+    let dt = (current.ts - prev.ts) as f64 / 1000.0;
+    let alpha = 1.0 - 0.5f64.powf(dt / half_life as f64);
+    let alpha = match Decimal::try_from(alpha) {
+        Ok(a) => a,
+        Err(_) => return current.price,
+    };
+    prev.price + alpha * (current.price - prev.price)
+}
+
+/// Each sample is weighted by the time it was in effect: until the next
+/// sample, and for the newest until `end`.
+fn twap(samples: &VecDeque<Sample>, end: u64) -> Decimal {
+    let len = samples.len();
+    let mut weighted = Decimal::ZERO;
+    let mut weight_sum = Decimal::ZERO;
+    for i in 0..len {
+        let until = samples.get(i + 1).map_or(end, |next| next.ts);
+        let w = Decimal::from(until.saturating_sub(samples[i].ts));
+        weighted += samples[i].price * w;
+        weight_sum += w;
+    }
+
+    if weight_sum.is_zero() {
+        return samples[len - 1].price;
+    }
+    weighted / weight_sum
+}
+
+impl Smoother {
+    /// Loads the history left by a previous run. A missing or unreadable file
+    /// starts an empty history.
+    pub fn load(path: &str) -> Smoother {
+        let saved: Saved = match std::fs::read(path) {
+            Ok(bytes) => match serde_json::from_slice(&bytes) {
+                Ok(h) => h,
+                Err(e) => {
+                    warn!("history {} unreadable: {}", path, e);
+                    Saved::default()
+                }
+            },
+            Err(_) => Saved::default(),
+        };
+        Smoother {
+            path: path.to_owned(),
+            history: saved.history,
+            published: saved.published,
+        }
+    }
+
+    /// Writes the history through a temporary file so a crash mid-write never
+    /// leaves a truncated file behind.
+    pub fn save(&self) -> Result<()> {
+        let tmp = format!("{}.tmp", self.path);
+        let saved = SavedRef {
+            history: &self.history,
+            published: &self.published,
+        };
+        std::fs::write(&tmp, serde_json::to_vec(&saved)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Keeps one published price per hour for the last day.
+    pub fn record_published(&mut self, symbol: &str, ts: u64, price: Decimal) {
+        let samples = self
+            .published
+            .entry(symbol.to_owned())
+            .or_insert_with(VecDeque::new);
+
+        samples.retain(|s| s.ts <= ts && ts - s.ts <= DAY + HOUR);
+        match samples.back() {
+            Some(last) if ts - last.ts < HOUR => {}
+            _ => samples.push_back(Sample { ts, price }),
+        }
+    }
+
+    /// Returns the oldest published price within the last day.
+    pub fn reference_24h(&self, symbol: &str, ts: u64) -> Option<Decimal> {
+        let samples = self.published.get(symbol)?;
+        samples
+            .iter()
+            .find(|s| s.ts <= ts && ts - s.ts <= DAY)
+            .map(|s| s.price)
+    }
+
+    /// Records `price` observed at `ts` (ms) and returns the smoothed value.
+    /// The EMA decays old prices by the time elapsed. The TWAP drops samples
+    /// older than twice its window, so a long outage does not drag stale
+    /// prices into the average, and counts the newest until the next round.
+    pub fn apply(
+        &mut self,
+        symbol: &str,
+        method: &Method,
+        ts: u64,
+        price: Decimal,
+        interval: u64,
+    ) -> Decimal {
+        let current = Sample { ts, price };
+        match method {
+            Method::Ema { half_life } => {
+                let prev = self.history.ema.get(symbol).copied();
+                let value = ema(prev, current, *half_life);
+                self.history
+                    .ema
+                    .insert(symbol.to_owned(), Sample { ts, price: value });
+                value
+            }
+            Method::Twap { rounds } => {
+                let rounds = (*rounds).max(1);
+                let max_age = rounds as u64 * interval * 1000 * 2;
+                let samples = self
+                    .history
+                    .samples
+                    .entry(symbol.to_owned())
+                    .or_insert_with(VecDeque::new);
+
+                samples.retain(|s| s.ts < ts && ts - s.ts <= max_age);
+                samples.push_back(current);
+                while samples.len() > rounds {
+                    samples.pop_front();
+                }
+                twap(samples, ts + interval * 1000)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: u64 = 1000;
+
+    fn sample(ts: u64, price: u64) -> Sample {
+        Sample {
+            ts,
+            price: Decimal::from(price),
+        }
+    }
+
+    #[test]
+    fn ema_moves_by_elapsed_half_lives() {
+        let prev = Some(sample(0, 100));
+        assert_eq!(ema(prev, sample(60 * SEC, 200), 60), Decimal::from(150));
+        let value = ema(prev, sample(120 * SEC, 200), 60);
+        assert_eq!(value.round_dp(8), Decimal::from(175));
+    }
+
+    #[test]
+    fn ema_starts_from_the_current_price() {
+        assert_eq!(ema(None, sample(0, 200), 60), Decimal::from(200));
+        // No time passed, or no smoothing configured.
+        let prev = Some(sample(SEC, 100));
+        assert_eq!(ema(prev, sample(SEC, 200), 60), Decimal::from(200));
+        assert_eq!(ema(prev, sample(2 * SEC, 200), 0), Decimal::from(200));
+    }
+
+    #[test]
+    fn twap_weights_by_time_in_effect() {
+        let samples = VecDeque::from(vec![
+            sample(0, 100),
+            sample(10, 200),
+            sample(40, 300),
+        ]);
+        // 100 for 10, 200 for 30 and 300 for the last 10.
+        assert_eq!(twap(&samples, 50), Decimal::from(200));
+        // The newest has not been in effect yet.
+        assert_eq!(twap(&samples, 40), Decimal::from(175));
+        assert_eq!(
+            twap(&VecDeque::from(vec![sample(5, 7)]), 5),
+            Decimal::from(7)
+        );
+    }
+
+    #[test]
+    fn apply_twap_keeps_the_window() {
+        let mut smoother = Smoother::default();
+        let method = Method::Twap { rounds: 2 };
+        let mut apply = |ts, price| {
+            smoother.apply("BTC", &method, ts, Decimal::from(price), 10)
+        };
+        assert_eq!(apply(0, 100), Decimal::from(100));
+        assert_eq!(apply(10 * SEC, 200), Decimal::from(150));
+        // Only the last two rounds count.
+        assert_eq!(apply(20 * SEC, 400), Decimal::from(300));
+        // After an outage the stale samples are dropped.
+        assert_eq!(apply(1000 * SEC, 50), Decimal::from(50));
+    }
+
+    #[test]
+    fn apply_ema_carries_the_average() {
+        let mut smoother = Smoother::default();
+        let method = Method::Ema { half_life: 10 };
+        let mut apply = |ts, price| {
+            smoother.apply("BTC", &method, ts, Decimal::from(price), 10)
+        };
+        assert_eq!(apply(0, 100), Decimal::from(100));
+        assert_eq!(apply(10 * SEC, 200), Decimal::from(150));
+        assert_eq!(apply(20 * SEC, 150), Decimal::from(150));
+    }
+}
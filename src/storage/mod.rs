@@ -1,19 +1,25 @@
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+use crate::decimal::Decimal;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Ticker {
-    pub prices: Vec<f64>,
-    pub volumes: Vec<f64>,
+    pub prices: Vec<Decimal>,
+    pub volumes: Vec<Decimal>,
+    pub timestamps: Vec<u64>,
 }
 
 impl Ticker {
     pub fn new(token_size: usize) -> Self {
         Ticker {
-            prices: vec![0.0f64; token_size],
-            volumes: vec![0.0f64; token_size],
+            prices: vec![Decimal::ZERO; token_size],
+            volumes: vec![Decimal::ZERO; token_size],
+            timestamps: vec![0u64; token_size],
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Storage {
     pub tickers: Vec<Ticker>,
 }